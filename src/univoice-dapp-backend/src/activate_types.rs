@@ -1,7 +1,7 @@
 use std::borrow::{Borrow, BorrowMut};
 use candid::{CandidType, Deserialize, Nat, Principal};
 use serde::Serialize;
-use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
 use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
 use icrc_ledger_types::icrc1::account::Account;
 use icrc_ledger_types::icrc1::transfer::{BlockIndex, NumTokens, TransferArg, TransferError};
@@ -14,6 +14,7 @@ use rand::rngs::StdRng;
 
 // Import from buss_types
 use crate::buss_types::{CustomInfo, get_custom_info};
+use crate::memory::MEMORY_MANAGER;
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
@@ -72,9 +73,6 @@ impl Storable for TaskRewardRecord {
 }
 
 thread_local! {
-    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = 
-        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
-
     static REWARD_RECORDS: RefCell<StableBTreeMap<String, InviteRewardRecord, Memory>> = RefCell::new(
         StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
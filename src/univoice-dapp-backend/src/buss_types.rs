@@ -1,19 +1,26 @@
 use std::borrow::{Borrow, BorrowMut};
 use candid::{CandidType, Deserialize};
 use serde::Serialize;
-use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
 use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, StableVec, storable::Bound};
 use std::cell::RefCell;
 
 use rand::Rng;
 use crate::constants::INVITE_REWARD;
+use crate::config_types::{ConfigValueType, infer_value_type, validate_config_value, validate_value_type};
+use crate::config_history::{self, ConfigAction};
+use crate::canister_registry::{self, CanisterRole};
 use std::option::Option;
 use std::collections::HashMap;
+use crate::memory::MEMORY_MANAGER;
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 // Define TokenAmount as a numeric type for storing token amounts
 type TokenAmount = u64;
 
+const COMMON_INFO_MAX_SIZE: u32 = 4096;
+// Room kept for the version string and private flag an entry gets when it is stored
+const COMMON_INFO_VERSION_RESERVE: usize = 64;
 
 #[derive(Clone, CandidType, Deserialize, Serialize)]
 pub struct CommonInfoCfg {
@@ -22,6 +29,10 @@ pub struct CommonInfoCfg {
     pub content: String,
     pub version: String,
     pub isvalid: bool,
+    // Declared value type; entries written before typing existed have none
+    pub value_type: Option<ConfigValueType>,
+    // Private entries are left out of the public view
+    pub is_private: Option<bool>,
 }
 
 impl Storable for CommonInfoCfg {
@@ -35,18 +46,20 @@ impl Storable for CommonInfoCfg {
     }
 
     const BOUND: Bound = Bound::Bounded {
-        max_size: 4096,
+        max_size: COMMON_INFO_MAX_SIZE,
         is_fixed_size: false,
     };
 }
 
 impl CommonInfoCfg {
-    pub fn create_info(key: String, content: String) -> Self {
+    pub fn create_info(key: String, content: String, value_type: Option<ConfigValueType>) -> Self {
         CommonInfoCfg {
             key,
             content,
             version: "1.0.0".to_string(), // Start with initial version
             isvalid: true,
+            value_type,
+            is_private: None,
         }
     }
 
@@ -54,6 +67,10 @@ impl CommonInfoCfg {
         self.isvalid
     }
 
+    pub fn is_private(&self) -> bool {
+        self.is_private.unwrap_or(false)
+    }
+
 
     pub fn get_info_version(&self) -> &str {
         &self.version
@@ -83,6 +100,10 @@ impl CommonInfoCfg {
         self.isvalid = false;
     }
 
+    pub fn mark_as_valid(&mut self) {
+        self.isvalid = true;
+    }
+
     // Compare versions (helper function)
     fn compare_versions(v1: &str, v2: &str) -> std::cmp::Ordering {
         let v1_parts: Vec<u32> = v1.split('.').map(|s| s.parse().unwrap_or(0)).collect();
@@ -165,9 +186,6 @@ impl CustomInfo {
 
 
 thread_local! {
-    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = 
-        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
-    
     static COMMON_INFO_MAP: RefCell<StableBTreeMap<String, CommonInfoCfg, Memory>> = RefCell::new(
         StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0)))
@@ -212,13 +230,66 @@ thread_local! {
     );
 }
 
-pub fn add_info_item(key: String, content: String) -> Result<(), String> {
+// Resolves the type of an entry and validates the content. Without a declared type an existing
+// key keeps its stored type and only new keys get one inferred from their name, so keys written
+// before types existed keep accepting their old values.
+fn typed_info(key: String, content: String, value_type: Option<ConfigValueType>) -> Result<CommonInfoCfg, String> {
+    if key.is_empty() || key.contains('\0') {
+        return Err("Key cannot be empty or contain NUL".to_string());
+    }
+    let value_type = match value_type {
+        Some(value_type) => Some(value_type),
+        None => match COMMON_INFO_MAP.with(|store| store.borrow().get(&key)) {
+            Some(existing) => existing.value_type,
+            None => infer_value_type(&key),
+        },
+    };
+    if let Some(value_type) = &value_type {
+        validate_value_type(value_type)?;
+        validate_config_value(value_type, &content)
+            .map_err(|e| format!("Invalid value for {}: {}", key, e))?;
+    }
+    let info = CommonInfoCfg::create_info(key, content, value_type);
+    check_info_size(&info)?;
+    Ok(info)
+}
+
+/// Rejects entries that would not fit their stable slot instead of trapping on insert
+pub fn check_info_size(info: &CommonInfoCfg) -> Result<(), String> {
+    let size = info.to_bytes().len() + COMMON_INFO_VERSION_RESERVE;
+    if size > COMMON_INFO_MAX_SIZE as usize {
+        return Err(format!(
+            "Config entry {} exceeds {} bytes",
+            info.key,
+            COMMON_INFO_MAX_SIZE as usize - COMMON_INFO_VERSION_RESERVE
+        ));
+    }
+    config_history::check_entry_size(info)
+}
+
+// Writes a new entry and records it; overwriting an existing key continues its version line
+fn put_info(store: &mut StableBTreeMap<String, CommonInfoCfg, Memory>, mut info: CommonInfoCfg) {
+    let previous = store.get(&info.key);
+    let action = match &previous {
+        Some(prev) => {
+            if info.is_private.is_none() {
+                info.is_private = prev.is_private;
+            }
+            info.version = prev.version.clone();
+            info.update_info(info.content.clone());
+            ConfigAction::Update
+        }
+        None => ConfigAction::Create,
+    };
+    config_history::record(action, &info, previous.as_ref());
+    store.insert(info.key.clone(), info);
+}
+
+pub fn add_info_item(key: String, content: String, value_type: Option<ConfigValueType>) -> Result<(), String> {
+    let info = typed_info(key, content, value_type)?;
     COMMON_INFO_MAP.with(|store| {
         let mut store = store.borrow_mut();
-        let info = CommonInfoCfg::create_info(key.clone(), content);
-
-        // Since COMMON_INFO_MAP is a StableBTreeMap, we can directly use insert
-        store.insert(key, info);
+        put_info(&mut store, info);
         Ok(())
     })
 }
@@ -227,6 +298,16 @@ pub fn add_info_item(key: String, content: String) -> Result<(), String> {
 pub struct BatchInfoItem {
     pub key: String,
     pub content: String,
+    pub value_type: Option<ConfigValueType>,
+    pub is_private: Option<bool>,
+}
+
+impl BatchInfoItem {
+    fn into_info(self) -> Result<CommonInfoCfg, String> {
+        let mut info = typed_info(self.key, self.content, self.value_type)?;
+        info.is_private = self.is_private;
+        Ok(info)
+    }
 }
 
 #[derive(Clone, CandidType, Deserialize, Serialize)]
@@ -235,6 +316,8 @@ pub struct TaskData {
     pub task_url: String,
     pub status: String,
     pub rewards: u64,
+    // Premium tasks can only be finished by holders of this entitlement
+    pub required_entitlement: Option<String>,
 }
 
 #[derive(Clone, CandidType, Deserialize, Serialize)]
@@ -274,24 +357,28 @@ fn init_user_tasks(principal_id: String) -> Result<(), String> {
                 task_url: "https://x.com/UNIVOICE_".to_string(),
                 status: "".to_string(),
                 rewards: 5000,
+                required_entitlement: None,
             },
             TaskData {
                 task_id: "Follow_TG_Community".to_string(),
                 task_url: "https://t.me/univoiceofficial".to_string(),
                 status: "".to_string(),
                 rewards: 5000,
+                required_entitlement: None,
             },
             TaskData {
                 task_id: "Follow_TG_Channel".to_string(),
                 task_url: "https://t.me/+S3WQWidjW9lkZTU1".to_string(),
                 status: "".to_string(),
                 rewards: 5000,
+                required_entitlement: None,
             },
             TaskData {
                 task_id: "Follow_YouTuBe".to_string(),
                 task_url: "https://youtube.com/@univoice-icp?si=v4LRyhzBbW1YZWLJ".to_string(),
                 status: "".to_string(),
                 rewards: 5000,
+                required_entitlement: None,
             },
         ];
 
@@ -358,12 +445,22 @@ pub fn update_task_status(principal_id: &str, task_id: &str, status: String) ->
             
             for task in &mut updated_tasks.tasks {
                 if task.task_id == task_id {
+                    let principal = candid::Principal::from_text(principal_id).ok();
+                    if status == "FINISH" {
+                        if let Some(required) = &task.required_entitlement {
+                            if !principal.map_or(false, |p| crate::entitlements::has_entitlement(&p, required)) {
+                                return Err(format!("Task {} requires the {} entitlement", task_id, required));
+                            }
+                        }
+                    }
                     task.status = status.clone();
                     task_found = true;
                     
                     // If the task is completed, add a task reward record
                     if status == "FINISH" {
-                        let reward_amount = task.rewards;
+                        let reward_amount = principal.map_or(task.rewards, |p| {
+                            crate::entitlements::apply_reward_multiplier(&p, task.rewards)
+                        });
                         match crate::activate_types::add_task_reward(
                             task_id.to_string(),
                             principal_id.to_string(),
@@ -393,11 +490,16 @@ pub fn update_task_status(principal_id: &str, task_id: &str, status: String) ->
 }
 
 pub fn batch_add_info_items(items: Vec<BatchInfoItem>) -> Result<(), String> {
+    // Validate every item before writing any, so a bad item leaves the store untouched
+    let infos = items
+        .into_iter()
+        .map(BatchInfoItem::into_info)
+        .collect::<Result<Vec<_>, String>>()?;
+
     COMMON_INFO_MAP.with(|store| {
         let mut store = store.borrow_mut();
-        for item in items {
-            let info = CommonInfoCfg::create_info(item.key.clone(), item.content);
-            store.insert(item.key, info);
+        for info in infos {
+            put_info(&mut store, info);
         }
         Ok(())
     })
@@ -411,6 +513,93 @@ pub fn get_info_by_key(key: &String) -> Option<CommonInfoCfg> {
     })
 }
 
+/// Valid entries whose key starts with `prefix`, in key order.
+/// Private entries are included only when `include_private` is set.
+pub fn list_info_by_prefix(prefix: &str, include_private: bool, skip: usize, take: usize) -> Vec<CommonInfoCfg> {
+    COMMON_INFO_MAP.with(|store| {
+        store
+            .borrow()
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(_, info)| info)
+            .filter(|info| info.check_validity() && (include_private || !info.is_private()))
+            .skip(skip)
+            .take(take)
+            .collect()
+    })
+}
+
+/// Every entry in a namespace (key prefix), including invalid and private ones
+pub fn export_info_namespace(prefix: &str) -> Vec<CommonInfoCfg> {
+    COMMON_INFO_MAP.with(|store| {
+        store
+            .borrow()
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(_, info)| info)
+            .collect()
+    })
+}
+
+/// Replaces a namespace with `items`: keys not in `items` are deleted, the rest are written.
+/// All items are validated first, so either the whole namespace is replaced or nothing changes.
+pub fn replace_info_namespace(prefix: String, items: Vec<BatchInfoItem>) -> Result<(), String> {
+    if prefix.is_empty() {
+        return Err("Namespace prefix cannot be empty".to_string());
+    }
+    let mut infos = Vec::with_capacity(items.len());
+    for item in items {
+        if !item.key.starts_with(&prefix) {
+            return Err(format!("Key {} is outside namespace {}", item.key, prefix));
+        }
+        if infos.iter().any(|info: &CommonInfoCfg| info.key == item.key) {
+            return Err(format!("Duplicate key {}", item.key));
+        }
+        infos.push(item.into_info()?);
+    }
+
+    let stale: Vec<CommonInfoCfg> = export_info_namespace(&prefix)
+        .into_iter()
+        .filter(|existing| !infos.iter().any(|info| info.key == existing.key))
+        .collect();
+
+    COMMON_INFO_MAP.with(|store| {
+        let mut store = store.borrow_mut();
+        for removed in stale {
+            store.remove(&removed.key);
+            config_history::record(ConfigAction::Delete, &removed, Some(&removed));
+        }
+        for info in infos {
+            // Unchanged entries keep their version
+            let unchanged = store.get(&info.key).map_or(false, |existing| {
+                existing.isvalid
+                    && existing.content == info.content
+                    && existing.value_type == info.value_type
+                    && existing.is_private == info.is_private
+            });
+            if !unchanged {
+                put_info(&mut store, info);
+            }
+        }
+        Ok(())
+    })
+}
+
+pub fn set_info_privacy(key: String, is_private: bool) -> Result<(), String> {
+    COMMON_INFO_MAP.with(|store| {
+        let mut store = store.borrow_mut();
+        let mut existing_info = store.get(&key).ok_or("Key not found")?;
+        if existing_info.is_private == Some(is_private) {
+            return Ok(());
+        }
+        let previous = existing_info.clone();
+        existing_info.is_private = Some(is_private);
+        config_history::record(ConfigAction::SetPrivacy, &existing_info, Some(&previous));
+        store.insert(key, existing_info);
+        Ok(())
+    })
+}
+
 pub fn batch_get_info(keys: Vec<String>) -> Vec<Option<CommonInfoCfg>> {
     COMMON_INFO_MAP.with(|store| {
         let store = store.borrow();
@@ -423,7 +612,7 @@ pub fn batch_get_info(keys: Vec<String>) -> Vec<Option<CommonInfoCfg>> {
     })
 }
 
-pub fn update_info_item(key: String, content: String) -> Result<(), String> {
+pub fn update_info_item(key: String, content: String, expected_version: Option<String>) -> Result<(), String> {
     COMMON_INFO_MAP.with(|store| {
         let mut store = store.borrow_mut();
 
@@ -431,7 +620,22 @@ pub fn update_info_item(key: String, content: String) -> Result<(), String> {
             if !existing_info.check_validity() {
                 return Err("Info item is not valid".to_string());
             }
+            if let Some(expected) = expected_version {
+                if expected != existing_info.version {
+                    return Err(format!(
+                        "Version conflict for {}: expected {}, current {}",
+                        key, expected, existing_info.version
+                    ));
+                }
+            }
+            if let Some(value_type) = &existing_info.value_type {
+                validate_config_value(value_type, &content)
+                    .map_err(|e| format!("Invalid value for {}: {}", key, e))?;
+            }
+            let previous = existing_info.clone();
             existing_info.update_info(content);
+            check_info_size(&existing_info)?;
+            config_history::record(ConfigAction::Update, &existing_info, Some(&previous));
             store.insert(key, existing_info);
             Ok(())
         } else {
@@ -440,6 +644,67 @@ pub fn update_info_item(key: String, content: String) -> Result<(), String> {
     })
 }
 
+/// Restores the content a key had at an earlier version. The restored content gets a new version.
+pub fn rollback_info_item(key: String, version: String) -> Result<CommonInfoCfg, String> {
+    COMMON_INFO_MAP.with(|store| {
+        let mut store = store.borrow_mut();
+        let mut existing_info = store.get(&key).ok_or("Key not found")?;
+        if CommonInfoCfg::compare_versions(&version, &existing_info.version) != std::cmp::Ordering::Less {
+            return Err(format!("Version {} is not older than current {}", version, existing_info.version));
+        }
+        let target = config_history::find_version(&key, &version)
+            .ok_or(format!("Version {} of {} not found in history", version, key))?;
+        if let Some(value_type) = &existing_info.value_type {
+            validate_config_value(value_type, &target.content)
+                .map_err(|e| format!("Version {} no longer matches type: {}", version, e))?;
+        }
+
+        let previous = existing_info.clone();
+        existing_info.update_info(target.content);
+        check_info_size(&existing_info)?;
+        config_history::record(ConfigAction::Rollback, &existing_info, Some(&previous));
+        store.insert(key, existing_info.clone());
+        Ok(existing_info)
+    })
+}
+
+/// Marks a key invalid (`valid == false`) or valid again. Invalid keys are hidden from readers.
+pub fn set_info_validity(key: String, valid: bool) -> Result<(), String> {
+    COMMON_INFO_MAP.with(|store| {
+        let mut store = store.borrow_mut();
+        let mut existing_info = store.get(&key).ok_or("Key not found")?;
+        if existing_info.check_validity() == valid {
+            return Err(format!("Info item is already {}", if valid { "valid" } else { "invalid" }));
+        }
+
+        let previous = existing_info.clone();
+        let action = if valid {
+            existing_info.mark_as_valid();
+            ConfigAction::Revalidate
+        } else {
+            existing_info.mark_as_invalid();
+            ConfigAction::Invalidate
+        };
+        config_history::record(action, &existing_info, Some(&previous));
+        store.insert(key, existing_info);
+        Ok(())
+    })
+}
+
+pub fn delete_info_item(key: String) -> Result<(), String> {
+    COMMON_INFO_MAP.with(|store| {
+        let mut store = store.borrow_mut();
+        let removed = store.remove(&key).ok_or("Key not found")?;
+        config_history::record(ConfigAction::Delete, &removed, Some(&removed));
+        Ok(())
+    })
+}
+
+/// Returns an entry regardless of validity, for administration
+pub fn get_info_item_any(key: &String) -> Option<CommonInfoCfg> {
+    COMMON_INFO_MAP.with(|store| store.borrow().get(key))
+}
+
 /** -------------------------------custom info--------------------------------- */
 pub fn find_custom_info_index(dapp_principal: &str, wallet_principal: &str) -> Option<u64> {
     CUSTOM_INFO_SET.with(|store| {
@@ -816,6 +1081,10 @@ pub fn generate_random_nickname() -> String {
 pub struct CanisterMapping {
     pub key: String,
     pub canister_id: String,
+    // Relative share of new users for bucket pool members (default 1)
+    pub weight: Option<u32>,
+    // Whether a bucket pool member still has capacity for new users (default true)
+    pub accepting_uploads: Option<bool>,
 }
 
 impl Storable for CanisterMapping {
@@ -839,15 +1108,45 @@ impl CanisterMapping {
         CanisterMapping {
             key,
             canister_id,
+            weight: None,
+            accepting_uploads: None,
         }
     }
+
+    pub fn is_bucket(&self) -> bool {
+        self.key == BUCKET_POOL_PREFIX || self.key.starts_with(&format!("{}_", BUCKET_POOL_PREFIX))
+    }
+
+    pub fn bucket_weight(&self) -> u32 {
+        self.weight.unwrap_or(1)
+    }
+
+    pub fn is_accepting_uploads(&self) -> bool {
+        self.accepting_uploads.unwrap_or(true)
+    }
 }
 
-// Add a new canister mapping
+// Bucket pool members use the key "bulklet" or "bulklet_<name>"
+pub const BUCKET_POOL_PREFIX: &str = "bulklet";
+
+// Add a new canister mapping. Role keys (frontend, bulklet, cluster, mugc, vmc) go through the registry.
 pub fn add_canister_mapping(key: String, canister_id: String) -> Result<(), String> {
     if key.is_empty() || canister_id.is_empty() {
         return Err("Key and canister_id cannot be empty".to_string());
     }
+    let principal = canister_registry::parse_canister_id(&canister_id)?;
+
+    match CanisterRole::from_key(&key) {
+        Some(role) => canister_registry::set_canister(role, principal),
+        None => store_canister_mapping(key, principal.to_text()),
+    }
+}
+
+// Write a mapping as-is, keeping pool settings of an existing entry
+pub fn store_canister_mapping(key: String, canister_id: String) -> Result<(), String> {
+    if key.is_empty() {
+        return Err("Key cannot be empty".to_string());
+    }
 
     CANISTER_MAPPINGS.with(|store| {
         let mut store = store.borrow_mut();
@@ -864,8 +1163,10 @@ pub fn add_canister_mapping(key: String, canister_id: String) -> Result<(), Stri
         }
         
         if let Some(index) = existing_index {
-            // Update existing mapping
-            let mapping = CanisterMapping::new(key.clone(), canister_id);
+            // Update existing mapping, keeping its pool settings
+            let mut mapping = store.get(index)
+                .unwrap_or_else(|| CanisterMapping::new(key.clone(), canister_id.clone()));
+            mapping.canister_id = canister_id;
             store.set(index, &mapping);
         } else {
             // Add new mapping
@@ -893,8 +1194,16 @@ fn find_canister_mapping_index(key: &str) -> Option<u64> {
     })
 }
 
-// Get a canister ID by key
+// Get a canister ID by key, resolving role keys through the registry
 pub fn get_canister_id(key: &str) -> Option<String> {
+    match CanisterRole::from_key(key) {
+        Some(role) => canister_registry::get_canister(role).map(|id| id.to_text()),
+        None => get_stored_canister_id(key).filter(|id| !id.is_empty()),
+    }
+}
+
+// Get a canister ID as stored in the mapping list
+pub fn get_stored_canister_id(key: &str) -> Option<String> {
     if key.is_empty() {
         return None;
     }
@@ -922,61 +1231,59 @@ pub fn get_all_canister_mappings() -> Vec<CanisterMapping> {
     })
 }
 
-// Specific canister type implementations
-
-// Frontend canister
-pub fn set_frontend_canister(canister_id: String) -> Result<(), String> {
-    add_canister_mapping("frontend".to_string(), canister_id)
-}
-
-pub fn get_frontend_canister() -> Option<String> {
-    get_canister_id("frontend")
-}
-
-// Bulklet canister
-pub fn set_bulklet_canister(canister_id: String) -> Result<(), String> {
-    add_canister_mapping("bulklet".to_string(), canister_id)
-}
-
-pub fn get_bulklet_canister() -> Option<String> {
-    get_canister_id("bulklet")
-}
-
-// Mugc/Muge canister
-pub fn set_mugc_canister(canister_id: String) -> Result<(), String> {
-    add_canister_mapping("mugc".to_string(), canister_id)
-}
-
-pub fn get_mugc_canister() -> Option<String> {
-    get_canister_id("mugc")
-}
+// Bucket pool
+pub fn add_bucket_to_pool(key: String, canister_id: String, weight: Option<u32>, accepting_uploads: Option<bool>) -> Result<(), String> {
+    let mapping = CanisterMapping {
+        key: key.clone(),
+        canister_id: canister_id.clone(),
+        weight,
+        accepting_uploads,
+    };
+    if !mapping.is_bucket() {
+        return Err(format!("Bucket pool keys must be \"{0}\" or start with \"{0}_\"", BUCKET_POOL_PREFIX));
+    }
+    if weight == Some(0) {
+        return Err("Bucket weight must be greater than zero".to_string());
+    }
+    candid::Principal::from_text(&canister_id)
+        .map_err(|e| format!("Invalid bucket principal: {}", e))?;
 
-// Cluster canister
-pub fn set_cluster_canister(canister_id: String) -> Result<(), String> {
-    add_canister_mapping("cluster".to_string(), canister_id)
+    add_canister_mapping(key.clone(), canister_id)?;
+    update_bucket_settings(&key, |m| {
+        m.weight = weight;
+        m.accepting_uploads = accepting_uploads;
+    })
 }
 
-pub fn get_cluster_canister() -> Option<String> {
-    get_canister_id("cluster")
+pub fn set_bucket_accepting_uploads(key: String, accepting_uploads: bool) -> Result<(), String> {
+    update_bucket_settings(&key, |m| m.accepting_uploads = Some(accepting_uploads))
 }
 
-
-// VMC canister
-pub fn set_vmc_canister(canister_id: String) -> Result<(), String> {
-    add_canister_mapping("vmc".to_string(), canister_id)
+fn update_bucket_settings(key: &str, update: impl FnOnce(&mut CanisterMapping)) -> Result<(), String> {
+    let index = find_canister_mapping_index(key).ok_or(format!("Bucket {} not found", key))?;
+    CANISTER_MAPPINGS.with(|store| {
+        let mut store = store.borrow_mut();
+        let mut mapping = store.get(index).ok_or("Failed to get canister mapping")?;
+        if !mapping.is_bucket() {
+            return Err(format!("{} is not a bucket pool member", key));
+        }
+        update(&mut mapping);
+        store.set(index, &mapping);
+        Ok(())
+    })
 }
 
-pub fn get_vmc_canister() -> Option<String> {
-    get_canister_id("vmc")
+/// Lists configured bucket pool members
+pub fn get_bucket_pool() -> Vec<CanisterMapping> {
+    get_all_canister_mappings()
+        .into_iter()
+        .filter(|m| m.is_bucket() && !m.canister_id.is_empty())
+        .collect()
 }
 
-// Initialize default canisters with empty IDs
+// Move legacy role mappings into the canister registry; empty placeholders are no longer stored
 pub fn initialize_default_canisters() -> Result<(), String> {
-    set_frontend_canister("".to_string())?;
-    set_bulklet_canister("".to_string())?;
-    set_mugc_canister("".to_string())?;
-    set_cluster_canister("".to_string())?;
-    set_vmc_canister("".to_string())?;
+    canister_registry::migrate_legacy_mappings();
     Ok(())
 }
 
@@ -0,0 +1,211 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
+use std::cell::RefCell;
+
+use crate::buss_types;
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Canisters the backend depends on, one principal per role
+#[derive(Clone, Copy, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum CanisterRole {
+    Frontend,
+    Bucket,
+    Cluster,
+    Mugc,
+    Vmc,
+}
+
+impl CanisterRole {
+    pub const ALL: [CanisterRole; 5] = [
+        CanisterRole::Frontend,
+        CanisterRole::Bucket,
+        CanisterRole::Cluster,
+        CanisterRole::Mugc,
+        CanisterRole::Vmc,
+    ];
+
+    /// Key the role used in the canister mapping list
+    pub fn key(&self) -> &'static str {
+        match self {
+            CanisterRole::Frontend => "frontend",
+            CanisterRole::Bucket => buss_types::BUCKET_POOL_PREFIX,
+            CanisterRole::Cluster => "cluster",
+            CanisterRole::Mugc => "mugc",
+            CanisterRole::Vmc => "vmc",
+        }
+    }
+
+    /// Roles voice storage cannot work without; the others are optional integrations
+    pub fn is_required(&self) -> bool {
+        matches!(self, CanisterRole::Bucket | CanisterRole::Cluster)
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|role| role.key() == key)
+    }
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct RegisteredCanister {
+    pub role: CanisterRole,
+    pub canister_id: Principal,
+    pub updated_by: Principal,
+    pub updated_at: u64,
+}
+
+impl Storable for RegisteredCanister {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize RegisteredCanister");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize RegisteredCanister")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 512,
+        is_fixed_size: false,
+    };
+}
+
+/// A change of the canister registered for a role; `canister_id` is None when the role was cleared
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct CanisterRoleChange {
+    pub id: u64,
+    pub role: CanisterRole,
+    pub previous: Option<Principal>,
+    pub canister_id: Option<Principal>,
+    pub changed_by: Principal,
+    pub changed_at: u64,
+}
+
+impl Storable for CanisterRoleChange {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize CanisterRoleChange");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize CanisterRoleChange")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 512,
+        is_fixed_size: false,
+    };
+}
+
+thread_local! {
+    // Keyed by CanisterRole::key()
+    static CANISTER_REGISTRY: RefCell<StableBTreeMap<String, RegisteredCanister, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17)))
+        )
+    );
+
+    static CANISTER_REGISTRY_HISTORY: RefCell<StableBTreeMap<u64, CanisterRoleChange, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18)))
+        )
+    );
+}
+
+pub fn parse_canister_id(canister_id: &str) -> Result<Principal, String> {
+    Principal::from_text(canister_id.trim()).map_err(|e| format!("Invalid canister principal {}: {}", canister_id, e))
+}
+
+fn record_change(role: CanisterRole, previous: Option<Principal>, canister_id: Option<Principal>) {
+    CANISTER_REGISTRY_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let id = history.last_key_value().map(|(id, _)| id + 1).unwrap_or(0);
+        history.insert(id, CanisterRoleChange {
+            id,
+            role,
+            previous,
+            canister_id,
+            changed_by: ic_cdk::caller(),
+            changed_at: ic_cdk::api::time(),
+        });
+    });
+}
+
+/// Registers the canister for a role. The legacy mapping list is kept in sync so the
+/// bucket pool and key-based lookups see the same principal.
+pub fn set_canister(role: CanisterRole, canister_id: Principal) -> Result<(), String> {
+    if canister_id == Principal::anonymous() || canister_id == Principal::management_canister() {
+        return Err(format!("{} is not a valid canister for {:?}", canister_id, role));
+    }
+    let previous = get_canister(role);
+    if previous == Some(canister_id) {
+        return Ok(());
+    }
+
+    buss_types::store_canister_mapping(role.key().to_string(), canister_id.to_text())?;
+    CANISTER_REGISTRY.with(|registry| {
+        registry.borrow_mut().insert(role.key().to_string(), RegisteredCanister {
+            role,
+            canister_id,
+            updated_by: ic_cdk::caller(),
+            updated_at: ic_cdk::api::time(),
+        });
+    });
+    record_change(role, previous, Some(canister_id));
+    Ok(())
+}
+
+pub fn remove_canister(role: CanisterRole) -> Result<(), String> {
+    let removed = CANISTER_REGISTRY.with(|registry| registry.borrow_mut().remove(&role.key().to_string()))
+        .ok_or(format!("No canister registered for {:?}", role))?;
+    buss_types::store_canister_mapping(role.key().to_string(), String::new())?;
+    record_change(role, Some(removed.canister_id), None);
+    Ok(())
+}
+
+pub fn get_canister(role: CanisterRole) -> Option<Principal> {
+    CANISTER_REGISTRY.with(|registry| registry.borrow().get(&role.key().to_string()).map(|c| c.canister_id))
+}
+
+pub fn list_canisters() -> Vec<RegisteredCanister> {
+    CANISTER_REGISTRY.with(|registry| registry.borrow().iter().map(|(_, c)| c).collect())
+}
+
+/// Registry changes, newest first, optionally for a single role
+pub fn list_history(role: Option<CanisterRole>) -> Vec<CanisterRoleChange> {
+    CANISTER_REGISTRY_HISTORY.with(|history| {
+        history
+            .borrow()
+            .iter()
+            .rev()
+            .filter(|(_, c)| role.map_or(true, |role| c.role == role))
+            .map(|(_, c)| c)
+            .collect()
+    })
+}
+
+/// Copies roles from the legacy mapping list into the registry. Empty placeholders and
+/// ids that do not parse as principals are skipped and logged.
+pub fn migrate_legacy_mappings() -> u32 {
+    let mut migrated = 0;
+    for role in CanisterRole::ALL {
+        if get_canister(role).is_some() {
+            continue;
+        }
+        let legacy = match buss_types::get_stored_canister_id(role.key()) {
+            Some(id) if !id.trim().is_empty() => id,
+            _ => continue,
+        };
+        match parse_canister_id(&legacy).and_then(|id| set_canister(role, id)) {
+            Ok(_) => migrated += 1,
+            Err(e) => ic_cdk::println!("Skipping legacy {:?} mapping: {}", role, e),
+        }
+    }
+    if migrated > 0 {
+        ic_cdk::println!("Migrated {} canister mappings into the registry", migrated);
+    }
+    migrated
+}
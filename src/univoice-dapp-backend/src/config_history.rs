@@ -0,0 +1,168 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
+use std::cell::RefCell;
+
+use crate::buss_types::CommonInfoCfg;
+use crate::config_types::ConfigValueType;
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const CONFIG_HISTORY_ENTRY_MAX_SIZE: u32 = 5120;
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum ConfigAction {
+    // State of a key that existed before history was recorded
+    Baseline,
+    Create,
+    Update,
+    Rollback,
+    Invalidate,
+    Revalidate,
+    Delete,
+    // Only the private flag changed; the version is kept
+    SetPrivacy,
+}
+
+/// Snapshot of a CommonInfoCfg entry after a change, with who made it and when
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct ConfigHistoryEntry {
+    pub id: u64,
+    pub key: String,
+    pub action: ConfigAction,
+    pub content: String,
+    pub version: String,
+    pub isvalid: bool,
+    pub value_type: Option<ConfigValueType>,
+    pub is_private: Option<bool>,
+    pub author: Principal,
+    pub timestamp: u64,
+}
+
+impl Storable for ConfigHistoryEntry {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize ConfigHistoryEntry");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize ConfigHistoryEntry")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: CONFIG_HISTORY_ENTRY_MAX_SIZE,
+        is_fixed_size: false,
+    };
+}
+
+thread_local! {
+    static CONFIG_HISTORY: RefCell<StableBTreeMap<u64, ConfigHistoryEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14)))
+        )
+    );
+
+    // "<key>\0<zero-padded entry id>" -> entry id, so one key's history is a contiguous range
+    static HISTORY_BY_KEY: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33)))
+        )
+    );
+}
+
+// NUL sorts before every other character, so the range of a key holds no other key's entries.
+// Config keys cannot contain NUL.
+fn key_prefix(key: &str) -> String {
+    format!("{}\0", key)
+}
+
+fn index_key(key: &str, id: u64) -> String {
+    format!("{}{:020}", key_prefix(key), id)
+}
+
+// Entry ids of a key, oldest first
+fn entry_ids(key: &str) -> Vec<u64> {
+    let prefix = key_prefix(key);
+    HISTORY_BY_KEY.with(|index| {
+        index
+            .borrow()
+            .range(prefix.clone()..)
+            .take_while(|(k, _)| k.starts_with(&prefix))
+            .map(|(_, id)| id)
+            .collect()
+    })
+}
+
+fn has_history(key: &str) -> bool {
+    let prefix = key_prefix(key);
+    HISTORY_BY_KEY.with(|index| {
+        index.borrow().range(prefix.clone()..).next().map_or(false, |(k, _)| k.starts_with(&prefix))
+    })
+}
+
+fn get_entry(id: u64) -> Option<ConfigHistoryEntry> {
+    CONFIG_HISTORY.with(|history| history.borrow().get(&id))
+}
+
+fn snapshot(id: u64, action: ConfigAction, info: &CommonInfoCfg, author: Principal, timestamp: u64) -> ConfigHistoryEntry {
+    ConfigHistoryEntry {
+        id,
+        key: info.key.clone(),
+        action,
+        content: info.content.clone(),
+        version: info.version.clone(),
+        isvalid: info.isvalid,
+        value_type: info.value_type.clone(),
+        is_private: info.is_private,
+        author,
+        timestamp,
+    }
+}
+
+/// Rejects an entry whose history snapshot would not fit its stable slot
+pub fn check_entry_size(info: &CommonInfoCfg) -> Result<(), String> {
+    let entry = snapshot(u64::MAX, ConfigAction::Update, info, ic_cdk::caller(), u64::MAX);
+    if entry.to_bytes().len() > CONFIG_HISTORY_ENTRY_MAX_SIZE as usize {
+        return Err(format!("History entry for {} exceeds {} bytes", info.key, CONFIG_HISTORY_ENTRY_MAX_SIZE));
+    }
+    Ok(())
+}
+
+/// Records the state of `info` after `action`, authored by the current caller.
+/// A key changed for the first time since history existed gets its prior state recorded as a baseline.
+pub fn record(action: ConfigAction, info: &CommonInfoCfg, previous: Option<&CommonInfoCfg>) {
+    let author = ic_cdk::caller();
+    let now = ic_cdk::api::time();
+    let baseline = previous.filter(|_| !has_history(&info.key));
+
+    CONFIG_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let mut next_id = history.last_key_value().map(|(id, _)| id + 1).unwrap_or(0);
+        let snapshots = baseline
+            .map(|prev| (ConfigAction::Baseline, prev))
+            .into_iter()
+            .chain(std::iter::once((action, info)));
+        for (action, info) in snapshots {
+            let entry = snapshot(next_id, action, info, author, now);
+            HISTORY_BY_KEY.with(|index| index.borrow_mut().insert(index_key(&entry.key, next_id), next_id));
+            history.insert(next_id, entry);
+            next_id += 1;
+        }
+    });
+}
+
+/// History of a key, newest first
+pub fn list_history(key: &str, skip: usize, take: usize) -> Vec<ConfigHistoryEntry> {
+    entry_ids(key).into_iter().rev().skip(skip).take(take).filter_map(get_entry).collect()
+}
+
+/// Latest recorded content of a key at `version`, ignoring deletions
+pub fn find_version(key: &str, version: &str) -> Option<ConfigHistoryEntry> {
+    entry_ids(key)
+        .into_iter()
+        .rev()
+        .filter_map(get_entry)
+        .find(|e| e.version == version && e.action != ConfigAction::Delete)
+}
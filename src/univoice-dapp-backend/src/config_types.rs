@@ -0,0 +1,322 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+use crate::buss_types::get_info_by_key;
+
+/// Declared type of a CommonInfoCfg value
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum ConfigValueType {
+    Text,
+    /// Unsigned 64-bit integer
+    Nat,
+    Principal,
+    /// Seconds, written as a number with an optional s/m/h/d/w unit suffix (e.g. "30d")
+    Duration,
+    /// JSON document, optionally validated against a JSON schema
+    Json(Option<String>),
+}
+
+/// Type applied to new keys that follow the repo's naming conventions when no type is declared.
+/// Existing keys are never retyped by inference.
+pub fn infer_value_type(key: &str) -> Option<ConfigValueType> {
+    if key.ends_with("_expired_duration") {
+        return Some(ConfigValueType::Duration);
+    }
+    if key.starts_with("nft_") || key.ends_with("_payment_ledger") {
+        return Some(ConfigValueType::Principal);
+    }
+    const NAT_SUFFIXES: [&str; 13] = [
+        "_nft_expired_at", "_quota_files", "_quota_bytes", "_secs", "_days", "_bytes", "_ms",
+        "_sample_rate", "_channels", "_count", "_price", "_bps", "_reward",
+    ];
+    if NAT_SUFFIXES.iter().any(|suffix| key.ends_with(suffix)) {
+        return Some(ConfigValueType::Nat);
+    }
+    None
+}
+
+pub fn parse_nat(content: &str) -> Result<u64, String> {
+    content
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| format!("'{}' is not a natural number", content))
+}
+
+pub fn parse_principal(content: &str) -> Result<Principal, String> {
+    Principal::from_text(content.trim()).map_err(|e| format!("'{}' is not a principal: {}", content, e))
+}
+
+/// Parses a duration in seconds; accepts a bare number or a number followed by s, m, h, d or w
+pub fn parse_duration_secs(content: &str) -> Result<u64, String> {
+    let content = content.trim();
+    let err = || format!("'{}' is not a duration (e.g. 3600, 60m, 30d)", content);
+    let (number, multiplier) = match content.chars().last() {
+        Some('s') => (&content[..content.len() - 1], 1),
+        Some('m') => (&content[..content.len() - 1], 60),
+        Some('h') => (&content[..content.len() - 1], 3600),
+        Some('d') => (&content[..content.len() - 1], 86_400),
+        Some('w') => (&content[..content.len() - 1], 604_800),
+        _ => (content, 1),
+    };
+    let number = number.parse::<u64>().map_err(|_| err())?;
+    number.checked_mul(multiplier).ok_or_else(err)
+}
+
+pub fn parse_json(content: &str) -> Result<JsonValue, String> {
+    serde_json::from_str(content).map_err(|e| format!("Invalid JSON: {}", e))
+}
+
+/// Validates content against its declared type
+pub fn validate_config_value(value_type: &ConfigValueType, content: &str) -> Result<(), String> {
+    match value_type {
+        ConfigValueType::Text => Ok(()),
+        ConfigValueType::Nat => parse_nat(content).map(|_| ()),
+        ConfigValueType::Principal => parse_principal(content).map(|_| ()),
+        ConfigValueType::Duration => parse_duration_secs(content).map(|_| ()),
+        ConfigValueType::Json(schema) => {
+            let value = parse_json(content)?;
+            if let Some(schema) = schema {
+                let schema = parse_json(schema).map_err(|e| format!("Invalid JSON schema: {}", e))?;
+                validate_json_schema(&value, &schema, "$")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Checks a schema is itself usable before it is stored
+pub fn validate_value_type(value_type: &ConfigValueType) -> Result<(), String> {
+    if let ConfigValueType::Json(Some(schema)) = value_type {
+        let schema = parse_json(schema).map_err(|e| format!("Invalid JSON schema: {}", e))?;
+        if !schema.is_object() {
+            return Err("JSON schema must be an object".to_string());
+        }
+    }
+    Ok(())
+}
+
+fn json_type_name(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "boolean",
+        JsonValue::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        JsonValue::Number(_) => "number",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object",
+    }
+}
+
+/// Validates a JSON value against the subset of JSON schema we rely on:
+/// type, enum, required, properties, additionalProperties (bool), items, minimum, maximum,
+/// minLength and maxLength.
+pub fn validate_json_schema(value: &JsonValue, schema: &JsonValue, path: &str) -> Result<(), String> {
+    let schema = match schema.as_object() {
+        Some(schema) => schema,
+        None => return Ok(()),
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let actual = json_type_name(value);
+        let matches = |t: &JsonValue| match t.as_str() {
+            Some("number") => actual == "number" || actual == "integer",
+            Some(t) => t == actual,
+            None => false,
+        };
+        let ok = match expected {
+            JsonValue::Array(types) => types.iter().any(matches),
+            t => matches(t),
+        };
+        if !ok {
+            return Err(format!("{}: expected {}, found {}", path, expected, actual));
+        }
+    }
+
+    if let Some(JsonValue::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            return Err(format!("{}: value {} is not one of {}", path, value, JsonValue::Array(allowed.clone())));
+        }
+    }
+
+    if let Some(n) = value.as_f64() {
+        if let Some(min) = schema.get("minimum").and_then(|m| m.as_f64()) {
+            if n < min {
+                return Err(format!("{}: {} is less than minimum {}", path, n, min));
+            }
+        }
+        if let Some(max) = schema.get("maximum").and_then(|m| m.as_f64()) {
+            if n > max {
+                return Err(format!("{}: {} is greater than maximum {}", path, n, max));
+            }
+        }
+    }
+
+    if let Some(s) = value.as_str() {
+        let len = s.chars().count() as u64;
+        if let Some(min) = schema.get("minLength").and_then(|m| m.as_u64()) {
+            if len < min {
+                return Err(format!("{}: string shorter than {}", path, min));
+            }
+        }
+        if let Some(max) = schema.get("maxLength").and_then(|m| m.as_u64()) {
+            if len > max {
+                return Err(format!("{}: string longer than {}", path, max));
+            }
+        }
+    }
+
+    if let Some(object) = value.as_object() {
+        if let Some(JsonValue::Array(required)) = schema.get("required") {
+            for field in required.iter().filter_map(|f| f.as_str()) {
+                if !object.contains_key(field) {
+                    return Err(format!("{}: missing required field '{}'", path, field));
+                }
+            }
+        }
+        let properties = schema.get("properties").and_then(|p| p.as_object());
+        for (field, field_value) in object {
+            match properties.and_then(|p| p.get(field)) {
+                Some(field_schema) => {
+                    validate_json_schema(field_value, field_schema, &format!("{}.{}", path, field))?;
+                }
+                None => {
+                    if schema.get("additionalProperties") == Some(&JsonValue::Bool(false)) {
+                        return Err(format!("{}: unexpected field '{}'", path, field));
+                    }
+                }
+            }
+        }
+    }
+
+    if let (Some(items), Some(item_schema)) = (value.as_array(), schema.get("items")) {
+        for (i, item) in items.iter().enumerate() {
+            validate_json_schema(item, item_schema, &format!("{}[{}]", path, i))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn config_content(key: &str) -> Option<String> {
+    get_info_by_key(&key.to_string()).map(|info| info.content)
+}
+
+fn required<T>(key: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<T, String> {
+    let content = config_content(key).ok_or_else(|| format!("Config key {} not found", key))?;
+    parse(&content).map_err(|e| format!("Config key {}: {}", key, e))
+}
+
+fn optional<T>(key: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<Option<T>, String> {
+    match config_content(key) {
+        Some(content) => parse(&content).map(Some).map_err(|e| format!("Config key {}: {}", key, e)),
+        None => Ok(None),
+    }
+}
+
+pub fn get_config_text(key: &str) -> Result<String, String> {
+    required(key, |c| Ok(c.to_string()))
+}
+
+pub fn get_config_nat(key: &str) -> Result<u64, String> {
+    required(key, parse_nat)
+}
+
+pub fn get_config_principal(key: &str) -> Result<Principal, String> {
+    required(key, parse_principal)
+}
+
+pub fn get_config_duration_secs(key: &str) -> Result<u64, String> {
+    required(key, parse_duration_secs)
+}
+
+pub fn get_config_json(key: &str) -> Result<JsonValue, String> {
+    required(key, parse_json)
+}
+
+/// Returns None when the key is missing and an error when its value is malformed
+pub fn get_optional_config_nat(key: &str) -> Result<Option<u64>, String> {
+    optional(key, parse_nat)
+}
+
+pub fn get_optional_config_principal(key: &str) -> Result<Option<Principal>, String> {
+    optional(key, parse_principal)
+}
+
+/// Returns `default` when the key is missing and an error when its value is malformed
+pub fn get_config_nat_or(key: &str, default: u64) -> Result<u64, String> {
+    optional(key, parse_nat).map(|v| v.unwrap_or(default))
+}
+
+pub fn get_config_duration_secs_or(key: &str, default: u64) -> Result<u64, String> {
+    optional(key, parse_duration_secs).map(|v| v.unwrap_or(default))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_duration_secs_accepts_units() {
+        assert_eq!(parse_duration_secs("3600"), Ok(3600));
+        assert_eq!(parse_duration_secs(" 45s "), Ok(45));
+        assert_eq!(parse_duration_secs("60m"), Ok(3600));
+        assert_eq!(parse_duration_secs("2h"), Ok(7200));
+        assert_eq!(parse_duration_secs("30d"), Ok(2_592_000));
+        assert_eq!(parse_duration_secs("1w"), Ok(604_800));
+    }
+
+    #[test]
+    fn parse_duration_secs_rejects_bad_input() {
+        assert!(parse_duration_secs("").is_err());
+        assert!(parse_duration_secs("d").is_err());
+        assert!(parse_duration_secs("-5").is_err());
+        assert!(parse_duration_secs("1.5h").is_err());
+        assert!(parse_duration_secs("10y").is_err());
+        assert!(parse_duration_secs(&format!("{}w", u64::MAX)).is_err());
+    }
+
+    #[test]
+    fn validate_json_schema_checks_types_and_ranges() {
+        let schema = json!({"type": "integer", "minimum": 1, "maximum": 10});
+        assert!(validate_json_schema(&json!(5), &schema, "$").is_ok());
+        assert!(validate_json_schema(&json!(0), &schema, "$").is_err());
+        assert!(validate_json_schema(&json!(11), &schema, "$").is_err());
+        assert!(validate_json_schema(&json!(2.5), &schema, "$").is_err());
+        assert!(validate_json_schema(&json!(2.5), &json!({"type": "number"}), "$").is_ok());
+        assert!(validate_json_schema(&json!(null), &json!({"type": ["string", "null"]}), "$").is_ok());
+    }
+
+    #[test]
+    fn validate_json_schema_checks_objects() {
+        let schema = json!({
+            "type": "object",
+            "required": ["name"],
+            "additionalProperties": false,
+            "properties": {
+                "name": {"type": "string", "minLength": 1, "maxLength": 8},
+                "level": {"enum": ["low", "high"]},
+                "tags": {"type": "array", "items": {"type": "string"}}
+            }
+        });
+        assert!(validate_json_schema(&json!({"name": "a", "level": "low", "tags": ["x"]}), &schema, "$").is_ok());
+        assert!(validate_json_schema(&json!({"level": "low"}), &schema, "$").is_err());
+        assert!(validate_json_schema(&json!({"name": ""}), &schema, "$").is_err());
+        assert!(validate_json_schema(&json!({"name": "a", "level": "mid"}), &schema, "$").is_err());
+        assert!(validate_json_schema(&json!({"name": "a", "extra": 1}), &schema, "$").is_err());
+
+        let err = validate_json_schema(&json!({"name": "a", "tags": ["x", 2]}), &schema, "$").unwrap_err();
+        assert!(err.starts_with("$.tags[1]"), "{}", err);
+    }
+
+    #[test]
+    fn validate_config_value_uses_declared_type() {
+        assert!(validate_config_value(&ConfigValueType::Nat, "12").is_ok());
+        assert!(validate_config_value(&ConfigValueType::Nat, "twelve").is_err());
+        assert!(validate_config_value(&ConfigValueType::Duration, "7d").is_ok());
+        let schema = Some(r#"{"type": "object", "required": ["a"]}"#.to_string());
+        assert!(validate_config_value(&ConfigValueType::Json(schema.clone()), r#"{"a": 1}"#).is_ok());
+        assert!(validate_config_value(&ConfigValueType::Json(schema), "{}").is_err());
+    }
+}
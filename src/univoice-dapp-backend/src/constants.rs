@@ -1 +1,79 @@
-pub const INVITE_REWARD: u64 = 5000;
\ No newline at end of file
+pub const INVITE_REWARD: u64 = 5000;
+
+// Default limits for uploaded voice files, overridable through CommonInfoCfg
+pub const DEFAULT_VOICE_MAX_SIZE_BYTES: u64 = 50 * 1024 * 1024;
+pub const DEFAULT_VOICE_MAX_DURATION_MS: u64 = 30 * 60 * 1000;
+pub const DEFAULT_VOICE_MIN_SAMPLE_RATE: u32 = 8_000;
+pub const DEFAULT_VOICE_MAX_SAMPLE_RATE: u32 = 192_000;
+pub const DEFAULT_VOICE_MAX_CHANNELS: u16 = 8;
+// Custom key/value pairs attached to a voice file, bounded so the record fits its stable slot
+pub const VOICE_CUSTOM_MAX_ENTRIES: usize = 16;
+pub const VOICE_CUSTOM_MAX_BYTES: usize = 2048;
+pub const DEFAULT_VOICE_ALLOWED_MIME_TYPES: &str = "audio/wav,audio/x-wav,audio/wave,audio/mpeg,audio/mp4,audio/aac,audio/ogg,audio/webm,audio/flac";
+
+// Default per-user storage quota before license upgrades
+pub const DEFAULT_STORAGE_QUOTA_FILES: u64 = 100;
+pub const DEFAULT_STORAGE_QUOTA_BYTES: u64 = 500 * 1024 * 1024;
+pub const DEFAULT_STORAGE_QUOTA_REFRESH_SECS: u64 = 3600;
+
+// Trashed voice assets can be restored for this many days before being purged
+pub const DEFAULT_VOICE_TRASH_RETENTION_DAYS: u64 = 30;
+pub const VOICE_TRASH_PURGE_INTERVAL_SECS: u64 = 3600;
+
+// ic-oss access tokens; policy templates use {folder} for the folder id
+pub const DEFAULT_ACCESS_TOKEN_TTL_SECS: u64 = 3600;
+pub const DEFAULT_ACCESS_TOKEN_REFRESH_MARGIN_SECS: u64 = 300;
+pub const DEFAULT_ACCESS_TOKEN_RW_POLICIES: &str = "Folder.Read:{folder} Folder.Write:{folder}";
+pub const DEFAULT_ACCESS_TOKEN_RO_POLICIES: &str = "Folder.Read:{folder}";
+
+// How often expired policy grants are detached
+pub const POLICY_GRANT_EXPIRY_INTERVAL_SECS: u64 = 300;
+
+// How often due scheduled config changes are applied
+pub const SCHEDULED_CONFIG_INTERVAL_SECS: u64 = 60;
+
+// Feature flag names checked by the canister
+pub const FEATURE_LICENSE_SHOP: &str = "license_shop";
+pub const FEATURE_LICENSE_MARKET: &str = "license_market";
+
+// How often registered canisters are pinged for the health report
+pub const HEALTH_CHECK_INTERVAL_SECS: u64 = 600;
+
+// How often lapsed licenses are flagged and their quota downgraded
+pub const LICENSE_EXPIRY_INTERVAL_SECS: u64 = 3600;
+
+// NFT collection metadata is cached; stale entries are refetched by the timer
+pub const DEFAULT_COLLECTION_METADATA_TTL_SECS: u64 = 900;
+pub const COLLECTION_METADATA_REFRESH_INTERVAL_SECS: u64 = 300;
+
+// Entitlements computed from held licenses are cached this long
+pub const DEFAULT_ENTITLEMENTS_TTL_SECS: u64 = 600;
+// Shortest interval between recomputations a caller can request
+pub const DEFAULT_ENTITLEMENTS_REFRESH_MIN_SECS: u64 = 60;
+
+// A pending purchase untouched this long has no call in flight and may be reconciled
+pub const PURCHASE_RECONCILE_AFTER_SECS: u64 = 600;
+
+// Share of a license resale paid to the treasury, in basis points
+pub const DEFAULT_LICENSE_ROYALTY_BPS: u64 = 500;
+
+// Limits on voice publication details
+pub const VOICE_TITLE_MAX_LEN: usize = 120;
+pub const VOICE_DESCRIPTION_MAX_LEN: usize = 500;
+pub const VOICE_MAX_TAGS: usize = 10;
+pub const VOICE_TAG_MAX_LEN: usize = 32;
+pub const VOICE_COVER_MAX_LEN: usize = 512;
+// The trending sort ranks this many of the newest public voices
+pub const VOICE_TRENDING_CANDIDATES: usize = 1000;
+
+// Repeat plays and shares by the same principal within this window are not counted
+pub const DEFAULT_VOICE_PLAY_DEDUP_SECS: u64 = 1800;
+// Limits on creator rewards for new listeners: per asset in total, and per asset in each window
+pub const DEFAULT_VOICE_LISTENER_REWARD_MAX_COUNT: u64 = 1000;
+pub const DEFAULT_VOICE_LISTENER_REWARD_WINDOW_SECS: u64 = 3600;
+pub const DEFAULT_VOICE_LISTENER_REWARD_WINDOW_MAX: u64 = 20;
+// Most asset ids accepted by the engagement batch queries
+pub const VOICE_ENGAGEMENT_BATCH_MAX: usize = 200;
+
+// Most files a listener's read token for another user's public recordings covers
+pub const VOICE_READ_TOKEN_MAX_FILES: usize = 200;
@@ -0,0 +1,201 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::config_types::get_config_nat_or;
+use crate::constants::{DEFAULT_ENTITLEMENTS_REFRESH_MIN_SECS, DEFAULT_ENTITLEMENTS_TTL_SECS};
+use crate::license_catalog;
+use crate::license_types;
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Entitlements checked by the canister. Catalog features are "<name>" or "<name>=<number>".
+pub const ENTITLEMENT_STORAGE_FILES: &str = "storage_files";
+pub const ENTITLEMENT_STORAGE_BYTES: &str = "storage_bytes";
+// Percentage applied to task rewards, e.g. 150 pays one and a half times the reward
+pub const ENTITLEMENT_REWARD_MULTIPLIER: &str = "reward_multiplier";
+
+/// A capability unlocked by a license; `source` is the collection that granted it
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Entitlement {
+    pub name: String,
+    pub value: Option<u64>,
+    pub source: Principal,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Entitlements {
+    pub principal: Principal,
+    pub entitlements: Vec<Entitlement>,
+    pub computed_at: u64,
+}
+
+impl Storable for Entitlements {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize Entitlements");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize Entitlements")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 8192,
+        is_fixed_size: false,
+    };
+}
+
+impl Entitlements {
+    pub fn get(&self, name: &str) -> Option<&Entitlement> {
+        self.entitlements.iter().find(|e| e.name == name)
+    }
+}
+
+thread_local! {
+    // Keyed by principal text
+    static ENTITLEMENTS: RefCell<StableBTreeMap<String, Entitlements, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27)))
+        )
+    );
+
+    // When each principal last asked for a recomputation. Only rate limits callers, so it
+    // lives on the heap and starts over after an upgrade.
+    static REFRESH_ATTEMPTS: RefCell<BTreeMap<Principal, u64>> = RefCell::new(BTreeMap::new());
+}
+
+/// Parses a catalog feature into its name and optional numeric value
+pub fn parse_feature(feature: &str) -> Result<(String, Option<u64>), String> {
+    let (name, value) = match feature.split_once('=') {
+        Some((name, value)) => {
+            let value = value.trim().parse::<u64>()
+                .map_err(|_| format!("Feature '{}' must be <name> or <name>=<number>", feature))?;
+            (name.trim(), Some(value))
+        }
+        None => (feature.trim(), None),
+    };
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(format!("Feature '{}' has an invalid name", feature));
+    }
+    Ok((name.to_string(), value))
+}
+
+fn ttl_ns() -> u64 {
+    get_config_nat_or("entitlements_ttl_secs", DEFAULT_ENTITLEMENTS_TTL_SECS)
+        .unwrap_or(DEFAULT_ENTITLEMENTS_TTL_SECS)
+        * 1_000_000_000
+}
+
+// Several licenses may grant the same entitlement; the highest value wins
+async fn compute(principal: Principal) -> Result<Vec<Entitlement>, String> {
+    let now_secs = ic_cdk::api::time() / 1_000_000_000;
+    let holdings = license_types::get_all_user_nfts(principal, Vec::new()).await?;
+    let mut entitlements: Vec<Entitlement> = Vec::new();
+    for holding in holdings.iter().filter(|h| h.is_active(now_secs)) {
+        let entry = match Principal::from_text(&holding.nft_colletion_id).ok().and_then(|c| license_catalog::get_entry(&c)) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        for feature in &entry.features {
+            let (name, value) = match parse_feature(feature) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    ic_cdk::println!("Ignoring feature of {}: {}", entry.collection_id, e);
+                    continue;
+                }
+            };
+            match entitlements.iter_mut().find(|e| e.name == name) {
+                Some(existing) if value > existing.value => {
+                    existing.value = value;
+                    existing.source = entry.collection_id;
+                }
+                Some(_) => {}
+                None => entitlements.push(Entitlement { name, value, source: entry.collection_id }),
+            }
+        }
+    }
+    Ok(entitlements)
+}
+
+pub fn get_cached(principal: &Principal) -> Option<Entitlements> {
+    ENTITLEMENTS.with(|store| store.borrow().get(&principal.to_text()))
+}
+
+/// Cached set if it is younger than `entitlements_ttl_secs`; an older one may grant
+/// licenses that have since lapsed or moved
+pub fn get_fresh(principal: &Principal) -> Option<Entitlements> {
+    let now = ic_cdk::api::time();
+    get_cached(principal).filter(|e| now.saturating_sub(e.computed_at) < ttl_ns())
+}
+
+/// Records a caller-requested recomputation, failing if the previous one was less than
+/// `entitlements_refresh_min_secs` ago. Keeps callers from forcing a round of collection
+/// calls on every request.
+pub fn throttle_refresh(principal: &Principal) -> Result<(), String> {
+    let min_interval = get_config_nat_or("entitlements_refresh_min_secs", DEFAULT_ENTITLEMENTS_REFRESH_MIN_SECS)
+        .unwrap_or(DEFAULT_ENTITLEMENTS_REFRESH_MIN_SECS)
+        .saturating_mul(1_000_000_000);
+    let now = ic_cdk::api::time();
+    REFRESH_ATTEMPTS.with(|attempts| {
+        let mut attempts = attempts.borrow_mut();
+        if let Some(last) = attempts.get(principal) {
+            if now.saturating_sub(*last) < min_interval {
+                return Err(format!(
+                    "Entitlements were refreshed less than {} seconds ago",
+                    min_interval / 1_000_000_000
+                ));
+            }
+        }
+        attempts.retain(|_, last| now.saturating_sub(*last) < min_interval);
+        attempts.insert(*principal, now);
+        Ok(())
+    })
+}
+
+/// Recomputes a principal's entitlements from the licenses they hold, unless the cached
+/// set is younger than `entitlements_ttl_secs` and `force` is not set. When the license
+/// lookup fails the previous set is kept.
+pub async fn refresh_entitlements(principal: Principal, force: bool) -> Entitlements {
+    let now = ic_cdk::api::time();
+    let cached = get_cached(&principal);
+    if let Some(cached) = &cached {
+        if !force && now.saturating_sub(cached.computed_at) < ttl_ns() {
+            return cached.clone();
+        }
+    }
+    match compute(principal).await {
+        Ok(entitlements) => {
+            let entitlements = Entitlements { principal, entitlements, computed_at: now };
+            ENTITLEMENTS.with(|store| store.borrow_mut().insert(principal.to_text(), entitlements.clone()));
+            entitlements
+        }
+        Err(e) => {
+            ic_cdk::println!("Failed to compute entitlements of {}: {}", principal, e);
+            cached.unwrap_or(Entitlements { principal, entitlements: Vec::new(), computed_at: 0 })
+        }
+    }
+}
+
+/// Whether the principal's cached entitlements include `name`. Synchronous so any module
+/// can call it; the cache is refreshed by quota refreshes, purchases and the expiry timer.
+/// A set older than `entitlements_ttl_secs` grants nothing until it is refreshed.
+pub fn has_entitlement(principal: &Principal, name: &str) -> bool {
+    get_fresh(principal).map_or(false, |e| e.get(name).is_some())
+}
+
+pub fn entitlement_value(principal: &Principal, name: &str) -> Option<u64> {
+    get_fresh(principal).and_then(|e| e.get(name).and_then(|e| e.value))
+}
+
+/// Applies the reward multiplier entitlement; amounts are unchanged without one
+pub fn apply_reward_multiplier(principal: &Principal, amount: u64) -> u64 {
+    match entitlement_value(principal, ENTITLEMENT_REWARD_MULTIPLIER) {
+        Some(percent) => amount.saturating_mul(percent) / 100,
+        None => amount,
+    }
+}
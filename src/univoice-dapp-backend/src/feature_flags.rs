@@ -0,0 +1,157 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
+use std::cell::RefCell;
+use sha2::{Digest, Sha256};
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const FEATURE_FLAG_MAX_SIZE: u32 = 8192;
+// Keeps a flag with a full allowlist and description well inside FEATURE_FLAG_MAX_SIZE
+const FEATURE_FLAG_NAME_MAX_LEN: usize = 64;
+const FEATURE_FLAG_DESCRIPTION_MAX_LEN: usize = 500;
+const FEATURE_FLAG_ALLOWLIST_MAX: usize = 200;
+
+/// A feature switch. When `enabled`, a `rollout_percent` below 100 turns it on for a
+/// stable subset of principals; principals in `allowlist` always get it.
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct FeatureFlag {
+    pub name: String,
+    pub enabled: bool,
+    pub rollout_percent: Option<u8>,
+    pub allowlist: Option<Vec<Principal>>,
+    pub description: Option<String>,
+    pub updated_by: Principal,
+    pub updated_at: u64,
+}
+
+impl Storable for FeatureFlag {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize FeatureFlag");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize FeatureFlag")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: FEATURE_FLAG_MAX_SIZE,
+        is_fixed_size: false,
+    };
+}
+
+impl FeatureFlag {
+    pub fn is_enabled_for(&self, principal: &Principal) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if self.allowlist.as_ref().map_or(false, |list| list.contains(principal)) {
+            return true;
+        }
+        match self.rollout_percent {
+            None => true,
+            Some(percent) => rollout_bucket(&self.name, principal) < percent as u64,
+        }
+    }
+}
+
+thread_local! {
+    static FEATURE_FLAGS: RefCell<StableBTreeMap<String, FeatureFlag, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16)))
+        )
+    );
+}
+
+// Places a principal in one of 100 buckets. Hashing in the flag name keeps rollouts of
+// different flags independent of each other.
+fn rollout_bucket(flag: &str, principal: &Principal) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.update(flag.as_bytes());
+    hasher.update(b":");
+    hasher.update(principal.as_slice());
+    let digest = hasher.finalize();
+    let mut head = [0u8; 8];
+    head.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(head) % 100
+}
+
+pub fn set_flag(
+    name: String,
+    enabled: bool,
+    rollout_percent: Option<u8>,
+    allowlist: Option<Vec<Principal>>,
+    description: Option<String>,
+) -> Result<FeatureFlag, String> {
+    if name.is_empty() {
+        return Err("Flag name cannot be empty".to_string());
+    }
+    if name.len() > FEATURE_FLAG_NAME_MAX_LEN {
+        return Err(format!("Flag name exceeds {} bytes", FEATURE_FLAG_NAME_MAX_LEN));
+    }
+    if rollout_percent.map_or(false, |p| p > 100) {
+        return Err("Rollout percent must be between 0 and 100".to_string());
+    }
+    let allowlist = allowlist.map(|mut list| {
+        list.sort();
+        list.dedup();
+        list
+    });
+    if allowlist.as_ref().map_or(false, |list| list.len() > FEATURE_FLAG_ALLOWLIST_MAX) {
+        return Err(format!("Allowlist exceeds {} principals", FEATURE_FLAG_ALLOWLIST_MAX));
+    }
+    if description.as_ref().map_or(false, |d| d.len() > FEATURE_FLAG_DESCRIPTION_MAX_LEN) {
+        return Err(format!("Description exceeds {} bytes", FEATURE_FLAG_DESCRIPTION_MAX_LEN));
+    }
+    let flag = FeatureFlag {
+        name: name.clone(),
+        enabled,
+        rollout_percent,
+        allowlist,
+        description,
+        updated_by: ic_cdk::caller(),
+        updated_at: ic_cdk::api::time(),
+    };
+    let size = flag.to_bytes().len();
+    if size > FEATURE_FLAG_MAX_SIZE as usize {
+        return Err(format!("Feature flag of {} bytes exceeds {} bytes", size, FEATURE_FLAG_MAX_SIZE));
+    }
+    FEATURE_FLAGS.with(|flags| {
+        flags.borrow_mut().insert(name, flag.clone());
+    });
+    Ok(flag)
+}
+
+pub fn delete_flag(name: &String) -> Result<(), String> {
+    FEATURE_FLAGS.with(|flags| {
+        flags.borrow_mut().remove(name).map(|_| ()).ok_or("Feature flag not found".to_string())
+    })
+}
+
+pub fn get_flag(name: &String) -> Option<FeatureFlag> {
+    FEATURE_FLAGS.with(|flags| flags.borrow().get(name))
+}
+
+pub fn list_flags() -> Vec<FeatureFlag> {
+    FEATURE_FLAGS.with(|flags| flags.borrow().iter().map(|(_, f)| f).collect())
+}
+
+/// Whether `name` is on for `principal`. Flags that were never defined resolve to `default`,
+/// so existing features can be gated without switching them off on deploy.
+pub fn is_feature_enabled(name: &str, principal: &Principal, default: bool) -> bool {
+    get_flag(&name.to_string())
+        .map(|flag| flag.is_enabled_for(principal))
+        .unwrap_or(default)
+}
+
+/// Errors when `name` is off for `principal`
+pub fn require_feature(name: &str, principal: &Principal, default: bool) -> Result<(), String> {
+    if is_feature_enabled(name, principal, default) {
+        Ok(())
+    } else {
+        Err(format!("Feature {} is not available", name))
+    }
+}
@@ -0,0 +1,209 @@
+use candid::{CandidType, Deserialize, Principal, Reserved};
+use serde::Serialize;
+use serde_bytes::ByteBuf;
+use ic_cdk::api::call::call;
+use ic_cdk::api::management_canister::main::{canister_info, CanisterInfoRequest};
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
+use std::cell::RefCell;
+
+use crate::buss_types;
+use crate::canister_registry::{self, CanisterRole};
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Result of the health checks for one dependency, keyed by its mapping key
+/// (the role key, or the pool key for additional buckets)
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct DependencyHealth {
+    pub key: String,
+    pub canister_id: Option<Principal>,
+    pub healthy: bool,
+    pub last_checked_at: u64,
+    // Round trip of the last check in nanoseconds
+    pub last_latency_ns: Option<u64>,
+    pub last_success_at: Option<u64>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<u64>,
+    pub consecutive_failures: u32,
+}
+
+impl Storable for DependencyHealth {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize DependencyHealth");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize DependencyHealth")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 2048,
+        is_fixed_size: false,
+    };
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct SystemHealth {
+    pub healthy: bool,
+    pub checked_at: Option<u64>,
+    pub dependencies: Vec<DependencyHealth>,
+}
+
+thread_local! {
+    static DEPENDENCY_HEALTH: RefCell<StableBTreeMap<String, DependencyHealth, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19)))
+        )
+    );
+}
+
+#[derive(Clone, Copy)]
+enum Probe {
+    Bucket,
+    Cluster,
+    CanisterInfo,
+}
+
+// Registered roles and required ones (a missing required role is a failure), plus pool
+// buckets beyond the default one. Optional roles that are not registered are not checked.
+fn targets() -> Vec<(String, Option<Principal>, Probe)> {
+    let mut targets: Vec<(String, Option<Principal>, Probe)> = CanisterRole::ALL
+        .into_iter()
+        .filter_map(|role| {
+            let canister_id = canister_registry::get_canister(role);
+            if canister_id.is_none() && !role.is_required() {
+                return None;
+            }
+            let probe = match role {
+                CanisterRole::Bucket => Probe::Bucket,
+                CanisterRole::Cluster => Probe::Cluster,
+                _ => Probe::CanisterInfo,
+            };
+            Some((role.key().to_string(), canister_id, probe))
+        })
+        .collect();
+
+    for mapping in buss_types::get_bucket_pool() {
+        if CanisterRole::from_key(&mapping.key).is_none() {
+            let bucket = Principal::from_text(&mapping.canister_id).ok();
+            targets.push((mapping.key, bucket, Probe::Bucket));
+        }
+    }
+    targets
+}
+
+// Cheapest call that proves the canister is reachable and running the expected code
+async fn probe(canister_id: Principal, probe: Probe) -> Result<(), String> {
+    match probe {
+        Probe::Bucket | Probe::Cluster => {
+            let method = match probe {
+                Probe::Bucket => "get_bucket_info",
+                _ => "get_cluster_info",
+            };
+            let (result,): (Result<Reserved, String>,) = call(canister_id, method, (None::<ByteBuf>,))
+                .await
+                .map_err(|(code, msg)| format!("{} failed: {:?} {}", method, code, msg))?;
+            result.map(|_| ()).map_err(|e| format!("{} returned error: {}", method, e))
+        }
+        Probe::CanisterInfo => {
+            let (info,) = canister_info(CanisterInfoRequest { canister_id, num_requested_changes: None })
+                .await
+                .map_err(|(code, msg)| format!("canister_info failed: {:?} {}", code, msg))?;
+            match info.module_hash {
+                Some(_) => Ok(()),
+                None => Err("Canister has no code installed".to_string()),
+            }
+        }
+    }
+}
+
+fn record(key: String, canister_id: Option<Principal>, started_at: u64, result: Result<(), String>) -> DependencyHealth {
+    let now = ic_cdk::api::time();
+    DEPENDENCY_HEALTH.with(|store| {
+        let mut store = store.borrow_mut();
+        let previous = store.get(&key);
+        let mut health = DependencyHealth {
+            key: key.clone(),
+            canister_id,
+            healthy: result.is_ok(),
+            last_checked_at: now,
+            last_latency_ns: canister_id.map(|_| now.saturating_sub(started_at)),
+            last_success_at: previous.as_ref().and_then(|p| p.last_success_at),
+            last_error: previous.as_ref().and_then(|p| p.last_error.clone()),
+            last_error_at: previous.as_ref().and_then(|p| p.last_error_at),
+            consecutive_failures: 0,
+        };
+        match result {
+            Ok(_) => health.last_success_at = Some(now),
+            Err(e) => {
+                health.last_error = Some(e);
+                health.last_error_at = Some(now);
+                health.consecutive_failures = previous.map_or(0, |p| p.consecutive_failures) + 1;
+            }
+        }
+        store.insert(key, health.clone());
+        health
+    })
+}
+
+/// Pings every dependency and records the outcome. Called from a timer and by controllers.
+pub async fn check_dependencies() -> SystemHealth {
+    let targets = targets();
+    // Forget buckets that have left the pool
+    DEPENDENCY_HEALTH.with(|store| {
+        let mut store = store.borrow_mut();
+        let stale: Vec<String> = store
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| !targets.iter().any(|(k, _, _)| k == key))
+            .collect();
+        for key in stale {
+            store.remove(&key);
+        }
+    });
+
+    let mut dependencies = Vec::new();
+    for (key, canister_id, kind) in targets {
+        let started_at = ic_cdk::api::time();
+        let result = match canister_id {
+            Some(canister_id) => probe(canister_id, kind).await,
+            None => Err("No canister registered".to_string()),
+        };
+        if let Err(e) = &result {
+            ic_cdk::println!("Health check for {} failed: {}", key, e);
+        }
+        dependencies.push(record(key, canister_id, started_at, result));
+    }
+    summarize(dependencies)
+}
+
+fn summarize(dependencies: Vec<DependencyHealth>) -> SystemHealth {
+    SystemHealth {
+        healthy: !dependencies.is_empty() && dependencies.iter().all(|d| d.healthy),
+        checked_at: dependencies.iter().map(|d| d.last_checked_at).max(),
+        dependencies,
+    }
+}
+
+/// Last recorded health of every dependency
+pub fn get_system_health() -> SystemHealth {
+    summarize(DEPENDENCY_HEALTH.with(|store| store.borrow().iter().map(|(_, h)| h).collect()))
+}
+
+/// Appends the last recorded failure of a dependency to a call error, so callers see why it failed
+pub fn with_health_hint(key: &str, err_msg: String) -> String {
+    let hint = DEPENDENCY_HEALTH.with(|store| {
+        store
+            .borrow()
+            .get(&key.to_string())
+            .filter(|h| !h.healthy)
+            .and_then(|h| h.last_error)
+    });
+    match hint {
+        Some(hint) => format!("{} ({} health check: {})", err_msg, key, hint),
+        None => err_msg,
+    }
+}
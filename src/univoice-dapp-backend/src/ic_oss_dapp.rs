@@ -11,11 +11,193 @@ use std::string::String;
 use ic_cdk::api::time;
 use serde_bytes::ByteBuf;
 use base64;
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use crate::constants::{
+    DEFAULT_ACCESS_TOKEN_TTL_SECS, DEFAULT_ACCESS_TOKEN_REFRESH_MARGIN_SECS,
+    DEFAULT_ACCESS_TOKEN_RW_POLICIES, DEFAULT_ACCESS_TOKEN_RO_POLICIES,
+};
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Folder provisioned for a user in an ic-oss bucket
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone)]
+pub struct UserFolder {
+    pub principal_id: Principal,
+    pub bucket_id: Principal,
+    pub folder_id: u32,
+    pub created_at: u64,
+}
+
+impl Storable for UserFolder {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize UserFolder");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize UserFolder")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+}
+
+// Argument and result of the ic-oss bucket create_folder method
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+struct CreateFolderInput {
+    parent: u32,
+    name: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+struct CreateFolderOutput {
+    id: u32,
+    created_at: u64,
+}
+
+// Fields of the ic-oss FolderInfo returned by list_folders that the backend reads
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+struct BucketFolderInfo {
+    id: u32,
+    name: String,
+}
+
+// Page size when searching the bucket root for an existing folder
+const LIST_FOLDERS_PAGE: u32 = 100;
+
+thread_local! {
+    static USER_FOLDERS: RefCell<StableBTreeMap<String, UserFolder, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12)))
+        )
+    );
+
+    // Principals whose folder is being created, to avoid creating it twice
+    static FOLDERS_IN_FLIGHT: RefCell<HashSet<Principal>> = RefCell::new(HashSet::new());
+}
+
+pub fn get_user_folder(principal_id: &Principal) -> Option<UserFolder> {
+    USER_FOLDERS.with(|store| store.borrow().get(&principal_id.to_text()))
+}
+
+/// Picks the bucket for a new user: the accepting pool member with the fewest
+/// provisioned folders relative to its weight
+pub fn select_bucket_for_new_user() -> Result<Principal, String> {
+    let mut folder_counts: HashMap<Principal, u64> = HashMap::new();
+    USER_FOLDERS.with(|store| {
+        for (_, folder) in store.borrow().iter() {
+            *folder_counts.entry(folder.bucket_id).or_insert(0) += 1;
+        }
+    });
+
+    crate::buss_types::get_bucket_pool()
+        .into_iter()
+        .filter(|m| m.is_accepting_uploads())
+        .filter_map(|m| {
+            let bucket = Principal::from_text(&m.canister_id).ok()?;
+            let count = folder_counts.get(&bucket).copied().unwrap_or(0);
+            // Scaled so that integer division keeps enough precision between weights
+            Some((count * 1_000_000 / m.bucket_weight() as u64, bucket))
+        })
+        .min_by_key(|(load, _)| *load)
+        .map(|(_, bucket)| bucket)
+        .ok_or_else(|| "No bucket is accepting new users".to_string())
+}
+
+/// Returns the user's folder, creating it on the least-loaded bucket on first access
+pub async fn ensure_user_folder(principal_id: Principal) -> Result<UserFolder, String> {
+    if let Some(folder) = get_user_folder(&principal_id) {
+        return Ok(folder);
+    }
+    let bucket_id = select_bucket_for_new_user()?;
+
+    let claimed = FOLDERS_IN_FLIGHT.with(|set| set.borrow_mut().insert(principal_id));
+    if !claimed {
+        return Err("Folder provisioning already in progress, please retry".to_string());
+    }
+
+    let name = principal_id.to_text();
+    let result = match create_bucket_folder(bucket_id, name.clone()).await {
+        Ok(id) => Ok(id),
+        // A previous attempt may have created the folder and lost the reply
+        Err(e) => match find_bucket_folder(bucket_id, &name).await {
+            Ok(Some(id)) => {
+                ic_cdk::println!("Reusing existing folder {} for {} after: {}", id, principal_id, e);
+                Ok(id)
+            }
+            Ok(None) => Err(e),
+            Err(lookup) => Err(format!("{}; looking up an existing folder failed: {}", e, lookup)),
+        },
+    };
+    FOLDERS_IN_FLIGHT.with(|set| set.borrow_mut().remove(&principal_id));
+
+    let folder = UserFolder {
+        principal_id,
+        bucket_id,
+        folder_id: result?,
+        created_at: time(),
+    };
+    USER_FOLDERS.with(|store| {
+        store.borrow_mut().insert(principal_id.to_text(), folder.clone());
+    });
+    ic_cdk::println!("Provisioned folder {} in bucket {} for {}", folder.folder_id, bucket_id, principal_id);
+    Ok(folder)
+}
+
+// Creates a folder under the bucket root. The backend canister must be a manager of the bucket.
+async fn create_bucket_folder(bucket: Principal, name: String) -> Result<u32, String> {
+    ic_cdk::println!("[CHECKPOINT] create_bucket_folder - START | bucket: {}, name: {}", bucket, name);
+
+    let input = CreateFolderInput { parent: 0, name };
+    let result: Result<(Result<CreateFolderOutput, String>,), _> =
+        call(bucket, "create_folder", (input, None::<ByteBuf>)).await;
+    let (result,) = result.map_err(|err| {
+        let err_msg = format!("Failed to call create_folder: {:?}", err);
+        ic_cdk::println!("[CHECKPOINT] create_bucket_folder - ERROR | {}", err_msg);
+        err_msg
+    })?;
+
+    let output = result.map_err(|err| {
+        ic_cdk::println!("[CHECKPOINT] create_bucket_folder - ERROR | Inner error: {}", err);
+        err
+    })?;
+    ic_cdk::println!("[CHECKPOINT] create_bucket_folder - END | folder_id: {}", output.id);
+    Ok(output.id)
+}
+
+// Finds a folder under the bucket root by name
+async fn find_bucket_folder(bucket: Principal, name: &str) -> Result<Option<u32>, String> {
+    let mut prev: Option<u32> = None;
+    loop {
+        let result: Result<(Result<Vec<BucketFolderInfo>, String>,), _> =
+            call(bucket, "list_folders", (0u32, prev, Some(LIST_FOLDERS_PAGE), None::<ByteBuf>)).await;
+        let (result,) = result.map_err(|err| format!("Failed to call list_folders: {:?}", err))?;
+        let folders = result?;
+        if let Some(folder) = folders.iter().find(|f| f.name == name) {
+            return Ok(Some(folder.id));
+        }
+        if folders.len() < LIST_FOLDERS_PAGE as usize {
+            return Ok(None);
+        }
+        prev = folders.last().map(|f| f.id);
+    }
+}
 
 #[derive(CandidType, Serialize, Deserialize, Debug)]
 pub struct AccessTokenResponse {
     pub access_token: String,
     pub folder: String,
+    // Bucket canister that holds the folder; the token's audience
+    pub bucket_id: Principal,
+    // Expiration of the token in seconds since the epoch
+    pub expires_at: u64,
 }
 
 pub async fn attach_policies(bucket_id: String, cluster_id: String, principal_id: String, policies: String) -> Result<(), String> {
@@ -56,7 +238,7 @@ pub async fn attach_policies(bucket_id: String, cluster_id: String, principal_id
 
     let (result,): (Result<(), String>,) = result
         .map_err(|err| {
-            let err_msg = format!("Failed to call attach_policies: {:?}", err);
+            let err_msg = crate::health::with_health_hint("cluster", format!("Failed to call attach_policies: {:?}", err));
             ic_cdk::println!("[CHECKPOINT] attach_policies - ERROR | {}", err_msg);
             err_msg
         })?;
@@ -103,7 +285,7 @@ pub async fn detach_policies(bucket_id: String, cluster_id: String, principal_id
 
     let (result,): (Result<(), String>,) = result
         .map_err(|err| {
-            let err_msg = format!("Failed to call detach_policies: {:?}", err);
+            let err_msg = crate::health::with_health_hint("cluster", format!("Failed to call detach_policies: {:?}", err));
             ic_cdk::println!("[CHECKPOINT] detach_policies - ERROR | {}", err_msg);
             err_msg
         })?;
@@ -112,49 +294,95 @@ pub async fn detach_policies(bucket_id: String, cluster_id: String, principal_id
     result
 }
 
-pub async fn get_access_token(wallet_principal: String) -> Result<AccessTokenResponse, String> {
-    ic_cdk::println!("[CHECKPOINT] get_access_token - START | wallet_principal: {}", wallet_principal);
-    
-    let subject = Principal::from_text(wallet_principal).map_err(|err| {
-        ic_cdk::println!("[CHECKPOINT] get_access_token - ERROR | Failed to parse subject principal: {:?}", err);
-        "Invalid subject principal".to_string()
-    })?;
-    
-    // Fetch bucket_id from canister mappings
-    let bucket_id = crate::buss_types::get_canister_id("bulklet");
-    ic_cdk::println!("[CHECKPOINT] get_access_token - Fetched bucket_id: {:?}", bucket_id);
-    
-    let bucket_id = bucket_id.ok_or_else(|| {
-        let err_msg = "Bulklet canister ID not configured".to_string();
-        ic_cdk::println!("[CHECKPOINT] get_access_token - ERROR | {}", err_msg);
-        err_msg
-    })?;
-    
-    let audience = Principal::from_text(bucket_id).map_err(|err| {
-        ic_cdk::println!("[CHECKPOINT] get_access_token - ERROR | Failed to parse bucket principal: {:?}", err);
-        "Invalid bucket principal".to_string()
-    })?;
-    
-    // Fetch cluster_id from canister mappings
+/// Scope of an ic-oss access token
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenScope {
+    /// Listener access to a folder
+    ReadOnly,
+    /// Owner access to their own folder
+    ReadWrite,
+}
+
+#[derive(Clone)]
+struct CachedToken {
+    access_token: String,
+    expires_at: u64,
+}
+
+thread_local! {
+    // Tokens issued by the cluster, keyed by (subject, audience, policies)
+    static TOKEN_CACHE: RefCell<HashMap<(Principal, Principal, String), CachedToken>> = RefCell::new(HashMap::new());
+}
+
+// Malformed values are logged and replaced by the default so they cannot lock users out
+fn config_u64(key: &str, default: u64) -> u64 {
+    crate::config_types::get_config_nat_or(key, default).unwrap_or_else(|e| {
+        ic_cdk::println!("{}, using default {}", e, default);
+        default
+    })
+}
+
+/// Builds the policy string for a scope from the configured template.
+/// Templates use `{folder}` as the placeholder for the folder id.
+pub fn scope_policies(scope: TokenScope, folder_id: u32) -> String {
+    let (key, default) = match scope {
+        TokenScope::ReadOnly => ("access_token_ro_policies", DEFAULT_ACCESS_TOKEN_RO_POLICIES),
+        TokenScope::ReadWrite => ("access_token_rw_policies", DEFAULT_ACCESS_TOKEN_RW_POLICIES),
+    };
+    crate::buss_types::get_info_by_key(&key.to_string())
+        .map(|info| info.content)
+        .filter(|template| !template.trim().is_empty())
+        .unwrap_or_else(|| default.to_string())
+        .replace("{folder}", &folder_id.to_string())
+}
+
+fn cluster_canister() -> Result<Principal, String> {
     let cluster_id = crate::buss_types::get_canister_id("cluster");
-    ic_cdk::println!("[CHECKPOINT] get_access_token - Fetched cluster_id: {:?}", cluster_id);
-    
+    ic_cdk::println!("[CHECKPOINT] cluster_canister - Fetched cluster_id: {:?}", cluster_id);
+
     let cluster_id = cluster_id.ok_or_else(|| {
         let err_msg = "Cluster canister ID not configured".to_string();
-        ic_cdk::println!("[CHECKPOINT] get_access_token - ERROR | {}", err_msg);
+        ic_cdk::println!("[CHECKPOINT] cluster_canister - ERROR | {}", err_msg);
         err_msg
     })?;
-    
-    let cluster_canister = Principal::from_text(cluster_id).map_err(|err| {
-        ic_cdk::println!("[CHECKPOINT] get_access_token - ERROR | Failed to parse cluster principal: {:?}", err);
+
+    Principal::from_text(cluster_id).map_err(|err| {
+        ic_cdk::println!("[CHECKPOINT] cluster_canister - ERROR | Failed to parse cluster principal: {:?}", err);
         "Invalid cluster principal".to_string()
-    })?;
+    })
+}
+
+/// Read permission on individual files of a folder, for listeners of another user's recordings
+pub fn file_read_policies(file_ids: &[u32]) -> String {
+    let ids: Vec<String> = file_ids.iter().map(|id| id.to_string()).collect();
+    format!("File.Read:{}", ids.join(","))
+}
 
-    let policies = "Folder.Read:0 Folder.Write:0".to_string();
+/// Issues a token for `subject` with `policies` on the bucket holding `folder`.
+/// A cached token is reused until it is within `access_token_refresh_margin_secs` of expiry.
+async fn issue_folder_token(subject: Principal, folder: &UserFolder, policies: String) -> Result<AccessTokenResponse, String> {
+    let audience = folder.bucket_id;
     let now_sec = time() / 1_000_000_000;
-    let expiration_sec = now_sec + 3600;
+    let margin = config_u64("access_token_refresh_margin_secs", DEFAULT_ACCESS_TOKEN_REFRESH_MARGIN_SECS);
+    let cache_key = (subject, audience, policies.clone());
+
+    if let Some(cached) = TOKEN_CACHE.with(|cache| cache.borrow().get(&cache_key).cloned()) {
+        if cached.expires_at > now_sec + margin {
+            ic_cdk::println!("[CHECKPOINT] issue_folder_token - Cache hit | subject: {}, expires_at: {}", subject, cached.expires_at);
+            return Ok(AccessTokenResponse {
+                access_token: cached.access_token,
+                folder: format!("{}/", folder.folder_id),
+                bucket_id: folder.bucket_id,
+                expires_at: cached.expires_at,
+            });
+        }
+    }
+
+    let cluster_canister = cluster_canister()?;
+    let ttl = config_u64("access_token_ttl_secs", DEFAULT_ACCESS_TOKEN_TTL_SECS);
+    let expiration_sec = now_sec + ttl;
     
-    ic_cdk::println!("[CHECKPOINT] get_access_token - Token params | policies: {}, now_sec: {}, expiration_sec: {}", 
+    ic_cdk::println!("[CHECKPOINT] issue_folder_token - Token params | policies: {}, now_sec: {}, expiration_sec: {}", 
                    policies, now_sec, expiration_sec);
 
     let token = Token {
@@ -162,50 +390,148 @@ pub async fn get_access_token(wallet_principal: String) -> Result<AccessTokenRes
         audience,
         policies,
     };
-    ic_cdk::println!("[CHECKPOINT] get_access_token - Token created | subject: {:?}, audience: {:?}, policies: {}", 
+    ic_cdk::println!("[CHECKPOINT] issue_folder_token - Token created | subject: {:?}, audience: {:?}, policies: {}", 
                    token.subject, token.audience, token.policies);
 
-    ic_cdk::println!("[CHECKPOINT] get_access_token - Calling admin_weak_access_token | cluster_canister: {:?}", cluster_canister);
+    ic_cdk::println!("[CHECKPOINT] issue_folder_token - Calling admin_weak_access_token | cluster_canister: {:?}", cluster_canister);
     let result: Result<(Result<ByteBuf, String>,), _> = call(
         cluster_canister, 
         "admin_weak_access_token", 
         (token, now_sec, expiration_sec)
     ).await;
-    
-    match &result {
-        Ok((inner_result,)) => {
-            match inner_result {
-                Ok(buf) => ic_cdk::println!("[CHECKPOINT] get_access_token - Got ByteBuf of size: {}", buf.len()),
-                Err(e) => ic_cdk::println!("[CHECKPOINT] get_access_token - Inner error: {}", e),
-            }
-        },
-        Err(err) => ic_cdk::println!("[CHECKPOINT] get_access_token - Call error: {:?}", err),
-    }
 
     let (result,): (Result<ByteBuf, String>,) = result
         .map_err(|err| {
-            let err_msg = format!("Failed to call admin_weak_access_token: {:?}", err);
-            ic_cdk::println!("[CHECKPOINT] get_access_token - ERROR | {}", err_msg);
+            let err_msg = crate::health::with_health_hint("cluster", format!("Failed to call admin_weak_access_token: {:?}", err));
+            ic_cdk::println!("[CHECKPOINT] issue_folder_token - ERROR | {}", err_msg);
             err_msg
         })?;
 
     match result {
         Ok(access_token) => {
-            ic_cdk::println!("[CHECKPOINT] get_access_token - Access token received | byte_length: {}", access_token.len());
+            ic_cdk::println!("[CHECKPOINT] issue_folder_token - Access token received | byte_length: {}", access_token.len());
             let access_token_str = base64::encode(access_token.as_ref());
+
+            TOKEN_CACHE.with(|cache| {
+                let mut cache = cache.borrow_mut();
+                // Drop expired entries so the cache does not grow without bound
+                cache.retain(|_, cached| cached.expires_at > now_sec);
+                cache.insert(cache_key, CachedToken {
+                    access_token: access_token_str.clone(),
+                    expires_at: expiration_sec,
+                });
+            });
             
             let response = AccessTokenResponse {
-                access_token: access_token_str.clone(),
-                folder: "0/".to_string(),
+                access_token: access_token_str,
+                folder: format!("{}/", folder.folder_id),
+                bucket_id: folder.bucket_id,
+                expires_at: expiration_sec,
             };
             
-            ic_cdk::println!("[CHECKPOINT] get_access_token - SUCCESS | token_length: {}, folder: {}", 
-                          access_token_str.len(), response.folder);
+            ic_cdk::println!("[CHECKPOINT] issue_folder_token - SUCCESS | folder: {}, expires_at: {}", 
+                          response.folder, response.expires_at);
             Ok(response)
         }
         Err(err) => {
-            ic_cdk::println!("[CHECKPOINT] get_access_token - ERROR | Inner error: {}", err);
+            ic_cdk::println!("[CHECKPOINT] issue_folder_token - ERROR | Inner error: {}", err);
             Err(err)
         }
     }
 }
+
+/// Issues a read/write token for the caller's own folder, provisioning it on first access
+pub async fn get_access_token(wallet_principal: String) -> Result<AccessTokenResponse, String> {
+    get_scoped_access_token(wallet_principal, TokenScope::ReadWrite, None, None).await
+}
+
+/// Issues a token for `wallet_principal`, which must be the caller. Tokens without an owner
+/// target the caller's own folder. Read-only tokens for `owner_principal` only cover that
+/// owner's published files: the one `asset_id` when it is public or unlisted, otherwise
+/// the owner's newest public files.
+pub async fn get_scoped_access_token(
+    wallet_principal: String,
+    scope: TokenScope,
+    owner_principal: Option<String>,
+    asset_id: Option<u64>,
+) -> Result<AccessTokenResponse, String> {
+    ic_cdk::println!("[CHECKPOINT] get_access_token - START | wallet_principal: {}, scope: {:?}, owner: {:?}",
+                   wallet_principal, scope, owner_principal);
+    
+    let subject = Principal::from_text(wallet_principal).map_err(|err| {
+        ic_cdk::println!("[CHECKPOINT] get_access_token - ERROR | Failed to parse subject principal: {:?}", err);
+        "Invalid subject principal".to_string()
+    })?;
+    // Tokens, and the folder behind them, are only issued to the caller itself
+    let caller = ic_cdk::caller();
+    if subject != caller && !ic_cdk::api::is_controller(&caller) {
+        return Err("Access tokens can only be requested for the caller's own principal".to_string());
+    }
+
+    let (folder, policies) = match (scope, owner_principal) {
+        (TokenScope::ReadOnly, Some(owner)) => {
+            let owner = Principal::from_text(owner).map_err(|err| {
+                ic_cdk::println!("[CHECKPOINT] get_access_token - ERROR | Failed to parse owner principal: {:?}", err);
+                "Invalid owner principal".to_string()
+            })?;
+            let folder = get_user_folder(&owner).ok_or("Owner has no folder provisioned")?;
+            let file_ids = crate::voice_catalog::readable_file_ids(owner, folder.bucket_id, asset_id)?;
+            (folder, file_read_policies(&file_ids))
+        }
+        (TokenScope::ReadWrite, Some(_)) => {
+            return Err("Read/write tokens are only issued for the caller's own folder".to_string());
+        }
+        (_, None) => {
+            // Scope the token to the user's own folder
+            let folder = ensure_user_folder(subject).await.map_err(|err| {
+                ic_cdk::println!("[CHECKPOINT] get_access_token - ERROR | Failed to provision folder: {}", err);
+                err
+            })?;
+            let policies = scope_policies(scope, folder.folder_id);
+            (folder, policies)
+        }
+    };
+
+    issue_folder_token(subject, &folder, policies).await
+}
+
+// Fields of the ic-oss bucket FileInfo the backend checks; the rest of the record is ignored
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone)]
+pub struct BucketFileInfo {
+    pub parent: u32,
+    pub size: u64,
+    // Bytes actually stored, counted by the bucket
+    pub filled: u64,
+}
+
+/// Reads a file's info from an ic-oss bucket. The backend canister must be a manager of the bucket.
+pub async fn get_bucket_file_info(bucket: Principal, file_id: u32) -> Result<BucketFileInfo, String> {
+    let result: Result<(Result<BucketFileInfo, String>,), _> =
+        call(bucket, "get_file_info", (file_id, None::<ByteBuf>)).await;
+    let (result,) = result.map_err(|err| format!("Failed to call get_file_info: {:?}", err))?;
+    result.map_err(|err| format!("File {} not found in bucket {}: {}", file_id, bucket, err))
+}
+
+/// Deletes a file from an ic-oss bucket. The backend canister must be a manager of the bucket.
+pub async fn delete_bucket_file(bucket: Principal, file_id: u32) -> Result<(), String> {
+    ic_cdk::println!("[CHECKPOINT] delete_bucket_file - START | bucket: {}, file_id: {}", bucket, file_id);
+
+    let result: Result<(Result<bool, String>,), _> = call(bucket, "delete_file", (file_id, None::<ByteBuf>)).await;
+    let (result,) = result.map_err(|err| {
+        let err_msg = format!("Failed to call delete_file: {:?}", err);
+        ic_cdk::println!("[CHECKPOINT] delete_bucket_file - ERROR | {}", err_msg);
+        err_msg
+    })?;
+
+    match result {
+        Ok(deleted) => {
+            // false means the file was already gone, which is fine
+            ic_cdk::println!("[CHECKPOINT] delete_bucket_file - END | deleted: {}", deleted);
+            Ok(())
+        }
+        Err(err) => {
+            ic_cdk::println!("[CHECKPOINT] delete_bucket_file - ERROR | Inner error: {}", err);
+            Err(err)
+        }
+    }
+}
\ No newline at end of file
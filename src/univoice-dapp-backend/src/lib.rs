@@ -58,12 +58,30 @@
 /// Returns a vector of InviteRewardRecord objects.
 
 /// Exports the Candid interface definition for the canister.
+mod memory;
 mod buss_types;
 mod activate_types;
 mod license_types;
 mod constants;
 mod ic_oss_dapp;
 mod voice_oss_type;
+mod storage_quota;
+mod config_types;
+mod config_history;
+mod policy_grants;
+mod scheduler;
+mod feature_flags;
+mod canister_registry;
+mod health;
+mod license_purchases;
+mod license_inventory;
+mod license_records;
+mod nft_collection_cache;
+mod license_catalog;
+mod entitlements;
+mod license_market;
+mod voice_catalog;
+mod voice_engagement;
 
 use candid::Principal;
 use getrandom::Error;
@@ -71,21 +89,29 @@ use rand::{RngCore, SeedableRng};
 use rand::rngs::SmallRng;
 use rand::Rng;
 use std::cell::RefCell;
+use std::time::Duration;
 use ic_cdk::api::time;
 use icrc_ledger_types::icrc1::account::Account;
 use icrc_ledger_types::icrc2::transfer_from::{TransferFromArgs, TransferFromError};
 use icrc_ledger_types::icrc1::transfer::{BlockIndex, NumTokens};
 
 use crate::voice_oss_type::{
-    VoiceAssetData, MetadataValue, ListVoiceOssParams, VoiceOssInfo,
-    store_voice_asset_data, get_voice_asset_data, delete_voice_asset_data,
+    VoiceAssetData, MetadataValue, ListVoiceOssParams, VoiceOssInfo, VoiceFileMetadata,
+    VoiceFileFilter, VoiceUploadLimits, DedupPolicy, validate_voice_metadata, find_duplicate_asset,
+    store_voice_asset_data, get_voice_asset_data, delete_voice_asset_data, restore_voice_asset_data,
     list_voice_files as oss_list_voice_files
 };
+use crate::constants::{
+    VOICE_TRASH_PURGE_INTERVAL_SECS, POLICY_GRANT_EXPIRY_INTERVAL_SECS, SCHEDULED_CONFIG_INTERVAL_SECS,
+    FEATURE_LICENSE_SHOP, FEATURE_LICENSE_MARKET, HEALTH_CHECK_INTERVAL_SECS, LICENSE_EXPIRY_INTERVAL_SECS,
+    COLLECTION_METADATA_REFRESH_INTERVAL_SECS,
+};
 
 use crate::license_types::{
     UserNFTsRequest, UserNFTsResponse, NFTCollection, UserLicenseRecord,
 };
 use crate::buss_types::InvitedUserResponse;
+use crate::canister_registry::CanisterRole;
 
 thread_local! {
     static RNG: RefCell<Option<SmallRng>> = RefCell::new(None);
@@ -109,6 +135,47 @@ fn custom_getrandom(buf: &mut [u8]) -> Result<(), Error> {
 #[ic_cdk::init]
 fn init() {
     init_rand();
+    start_timers();
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    init_rand();
+    canister_registry::migrate_legacy_mappings();
+    license_catalog::migrate_legacy_collections();
+    start_timers();
+}
+
+// Timers do not survive upgrades, so they are re-armed from both init and post_upgrade
+fn start_timers() {
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(VOICE_TRASH_PURGE_INTERVAL_SECS), || {
+        ic_cdk::spawn(async {
+            voice_oss_type::purge_expired_trash().await;
+        });
+    });
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(POLICY_GRANT_EXPIRY_INTERVAL_SECS), || {
+        ic_cdk::spawn(async {
+            policy_grants::detach_expired_grants().await;
+        });
+    });
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(SCHEDULED_CONFIG_INTERVAL_SECS), || {
+        scheduler::run_due_changes();
+    });
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(HEALTH_CHECK_INTERVAL_SECS), || {
+        ic_cdk::spawn(async {
+            health::check_dependencies().await;
+        });
+    });
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(LICENSE_EXPIRY_INTERVAL_SECS), || {
+        ic_cdk::spawn(async {
+            license_types::enforce_license_expiry().await;
+        });
+    });
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(COLLECTION_METADATA_REFRESH_INTERVAL_SECS), || {
+        ic_cdk::spawn(async {
+            nft_collection_cache::refresh_stale().await;
+        });
+    });
 }
 
 
@@ -126,16 +193,50 @@ fn is_called_by_dapp_frontend() -> Result<(), String> {
 }
 
 #[ic_cdk::update]
-async fn add_info_item(key: String, content: String) -> Result<(), String> {
-    ic_cdk::println!("CALL: add_info_item with key: {}", key);
+async fn add_info_item(key: String, content: String, value_type: Option<config_types::ConfigValueType>) -> Result<(), String> {
+    ic_cdk::println!("CALL: add_info_item with key: {}, type: {:?}", key, value_type);
     is_controller()?;
-    buss_types::add_info_item(key, content)
+    buss_types::add_info_item(key, content, value_type)
+}
+
+// Private config entries are only visible to controllers
+fn visible_info(info: Option<buss_types::CommonInfoCfg>) -> Option<buss_types::CommonInfoCfg> {
+    info.filter(|info| !info.is_private() || is_controller().is_ok())
 }
 
 #[ic_cdk::query]
 fn get_info_by_key(key: String) -> Option<buss_types::CommonInfoCfg> {
     ic_cdk::println!("CALL: get_info_by_key with key: {}", key);
-    buss_types::get_info_by_key(&key)
+    visible_info(buss_types::get_info_by_key(&key))
+}
+
+#[ic_cdk::query]
+fn list_info_by_prefix(prefix: String, page: Option<u32>, page_size: Option<u32>) -> Vec<buss_types::CommonInfoCfg> {
+    ic_cdk::println!("CALL: list_info_by_prefix with prefix: {}", prefix);
+    let page = page.unwrap_or(0) as usize;
+    let page_size = page_size.unwrap_or(50).min(200) as usize;
+    buss_types::list_info_by_prefix(&prefix, is_controller().is_ok(), page * page_size, page_size)
+}
+
+#[ic_cdk::query]
+fn export_info_namespace(prefix: String) -> Result<Vec<buss_types::CommonInfoCfg>, String> {
+    ic_cdk::println!("CALL: export_info_namespace with prefix: {}", prefix);
+    is_controller()?;
+    Ok(buss_types::export_info_namespace(&prefix))
+}
+
+#[ic_cdk::update]
+async fn replace_info_namespace(prefix: String, items: Vec<buss_types::BatchInfoItem>) -> Result<(), String> {
+    ic_cdk::println!("CALL: replace_info_namespace with prefix: {} and {} items", prefix, items.len());
+    is_controller()?;
+    buss_types::replace_info_namespace(prefix, items)
+}
+
+#[ic_cdk::update]
+async fn set_info_privacy(key: String, is_private: bool) -> Result<(), String> {
+    ic_cdk::println!("CALL: set_info_privacy with key: {}, private: {}", key, is_private);
+    is_controller()?;
+    buss_types::set_info_privacy(key, is_private)
 }
 
 #[ic_cdk::update]
@@ -149,13 +250,116 @@ async fn batch_add_info_items(items: Vec<buss_types::BatchInfoItem>) -> Result<(
 fn batch_get_info(keys: Vec<String>) -> Vec<Option<buss_types::CommonInfoCfg>> {
     ic_cdk::println!("CALL: batch_get_info with {} keys", keys.len());
     buss_types::batch_get_info(keys)
+        .into_iter()
+        .map(visible_info)
+        .collect()
+}
+
+#[ic_cdk::update]
+async fn update_info_item(key: String, content: String, expected_version: Option<String>) -> Result<(), String> {
+    ic_cdk::println!("CALL: update_info_item with key: {}, expected version: {:?}", key, expected_version);
+    is_controller()?;
+    buss_types::update_info_item(key, content, expected_version)
+}
+
+#[ic_cdk::query]
+fn get_info_item_admin(key: String) -> Result<Option<buss_types::CommonInfoCfg>, String> {
+    ic_cdk::println!("CALL: get_info_item_admin with key: {}", key);
+    is_controller()?;
+    Ok(buss_types::get_info_item_any(&key))
+}
+
+#[ic_cdk::query]
+fn list_info_history(key: String, page: Option<u32>, page_size: Option<u32>) -> Result<Vec<config_history::ConfigHistoryEntry>, String> {
+    ic_cdk::println!("CALL: list_info_history with key: {}", key);
+    is_controller()?;
+    let page = page.unwrap_or(0) as usize;
+    let page_size = page_size.unwrap_or(20).min(100) as usize;
+    Ok(config_history::list_history(&key, page * page_size, page_size))
+}
+
+#[ic_cdk::update]
+async fn rollback_info_item(key: String, version: String) -> Result<buss_types::CommonInfoCfg, String> {
+    ic_cdk::println!("CALL: rollback_info_item with key: {} to version: {}", key, version);
+    is_controller()?;
+    buss_types::rollback_info_item(key, version)
 }
 
 #[ic_cdk::update]
-async fn update_info_item(key: String, content: String) -> Result<(), String> {
-    ic_cdk::println!("CALL: update_info_item with key: {}", key);
+async fn invalidate_info_item(key: String) -> Result<(), String> {
+    ic_cdk::println!("CALL: invalidate_info_item with key: {}", key);
     is_controller()?;
-    buss_types::update_info_item(key, content)
+    buss_types::set_info_validity(key, false)
+}
+
+#[ic_cdk::update]
+async fn revalidate_info_item(key: String) -> Result<(), String> {
+    ic_cdk::println!("CALL: revalidate_info_item with key: {}", key);
+    is_controller()?;
+    buss_types::set_info_validity(key, true)
+}
+
+#[ic_cdk::update]
+async fn delete_info_item(key: String) -> Result<(), String> {
+    ic_cdk::println!("CALL: delete_info_item with key: {}", key);
+    is_controller()?;
+    buss_types::delete_info_item(key)
+}
+
+#[ic_cdk::update]
+async fn schedule_config_change(key: String, action: scheduler::ScheduledAction, execute_at: u64) -> Result<scheduler::ScheduledConfigChange, String> {
+    ic_cdk::println!("CALL: schedule_config_change for key: {} at: {}", key, execute_at);
+    is_controller()?;
+    scheduler::schedule_change(key, action, execute_at)
+}
+
+#[ic_cdk::update]
+async fn cancel_scheduled_config_change(id: u64) -> Result<(), String> {
+    ic_cdk::println!("CALL: cancel_scheduled_config_change with id: {}", id);
+    is_controller()?;
+    scheduler::cancel_change(id)
+}
+
+#[ic_cdk::query]
+fn list_scheduled_config_changes(pending_only: Option<bool>) -> Result<Vec<scheduler::ScheduledConfigChange>, String> {
+    ic_cdk::println!("CALL: list_scheduled_config_changes");
+    is_controller()?;
+    Ok(scheduler::list_changes(pending_only.unwrap_or(true)))
+}
+
+#[ic_cdk::update]
+async fn set_feature_flag(
+    name: String,
+    enabled: bool,
+    rollout_percent: Option<u8>,
+    allowlist: Option<Vec<Principal>>,
+    description: Option<String>,
+) -> Result<feature_flags::FeatureFlag, String> {
+    ic_cdk::println!("CALL: set_feature_flag {} enabled: {}, rollout: {:?}", name, enabled, rollout_percent);
+    is_controller()?;
+    feature_flags::set_flag(name, enabled, rollout_percent, allowlist, description)
+}
+
+#[ic_cdk::update]
+async fn delete_feature_flag(name: String) -> Result<(), String> {
+    ic_cdk::println!("CALL: delete_feature_flag {}", name);
+    is_controller()?;
+    feature_flags::delete_flag(&name)
+}
+
+#[ic_cdk::query]
+fn list_feature_flags() -> Result<Vec<feature_flags::FeatureFlag>, String> {
+    ic_cdk::println!("CALL: list_feature_flags");
+    is_controller()?;
+    Ok(feature_flags::list_flags())
+}
+
+/// Checks a flag for `principal`, or for the caller when none is given. Undefined flags are off.
+#[ic_cdk::query]
+fn is_feature_enabled(name: String, principal: Option<Principal>) -> bool {
+    ic_cdk::println!("CALL: is_feature_enabled {}", name);
+    let principal = principal.unwrap_or_else(ic_cdk::caller);
+    feature_flags::is_feature_enabled(&name, &principal, false)
 }
 
 #[ic_cdk::update]
@@ -239,9 +443,23 @@ async fn get_user_nfts(req: UserNFTsRequest) -> Result<UserNFTsResponse, String>
 }
 
 #[ic_cdk::query]
-async fn get_nft_collection(collection_id: String) -> Result<NFTCollection, String> {
+fn get_nft_collection(collection_id: String) -> Result<NFTCollection, String> {
     ic_cdk::println!("CALL: get_nft_collection with ID: {}", collection_id);
-    license_types::get_nft_collection(&collection_id).await
+    license_types::get_nft_collection(&collection_id)
+}
+
+/// Fetches a collection's metadata into the cache. Controllers always refetch any collection;
+/// other callers only collections in the license catalog, once the cached entry is past its TTL.
+#[ic_cdk::update]
+async fn refresh_nft_collection(collection_id: Principal) -> Result<NFTCollection, String> {
+    ic_cdk::println!("CALL: refresh_nft_collection with ID: {}", collection_id);
+    if is_controller().is_ok() {
+        nft_collection_cache::refresh(collection_id).await
+    } else if nft_collection_cache::is_known(&collection_id) {
+        nft_collection_cache::get_or_refresh(collection_id).await
+    } else {
+        Err(format!("Collection {} is not in the license catalog", collection_id))
+    }
 }
 
 #[ic_cdk::update]
@@ -249,15 +467,276 @@ async fn buy_nft_license(buyer: String, collection_id: String, quantity: u64) ->
     ic_cdk::println!("CALL: buy_nft_license for buyer: {}, collection: {}, quantity: {}", buyer, collection_id, quantity);
     is_called_by_dapp_frontend()?;
 
-    let _buyer_principal = Principal::from_text(&buyer)
+    let buyer_principal = Principal::from_text(&buyer)
         .map_err(|e| format!("Invalid buyer principal: {}", e))?;
+    // Payment is pulled from the buyer's approval, so only the buyer may check out
+    if ic_cdk::caller() != buyer_principal {
+        return Err("Licenses can only be bought by the buyer".to_string());
+    }
+    feature_flags::require_feature(FEATURE_LICENSE_SHOP, &buyer_principal, true)?;
 
     let transaction_records = license_types::buy_nft_license(&buyer, &collection_id, quantity).await?;
-    let collection = license_types::get_nft_collection(&collection_id).await?;
+    // The purchase just refreshed the cache if it was stale
+    let collection = license_types::get_nft_collection(&collection_id)?;
+    // New licenses unlock their quota and entitlements right away
+    storage_quota::refresh_user_quota(buyer_principal, true).await;
 
     Ok((transaction_records, collection))
 }
 
+/// License collections on sale, by tier; controllers may include those off sale
+#[ic_cdk::query]
+fn list_licenses(include_off_sale: Option<bool>) -> Vec<license_catalog::LicenseCatalogEntry> {
+    ic_cdk::println!("CALL: list_licenses");
+    let include_off_sale = include_off_sale.unwrap_or(false) && is_controller().is_ok();
+    license_catalog::list_entries(include_off_sale)
+}
+
+#[ic_cdk::query]
+fn get_license_catalog_entry(collection_id: Principal) -> Option<license_catalog::LicenseCatalogEntry> {
+    ic_cdk::println!("CALL: get_license_catalog_entry for: {}", collection_id);
+    license_catalog::get_entry(&collection_id).filter(|e| e.on_sale || is_controller().is_ok())
+}
+
+#[ic_cdk::update]
+fn add_license_catalog_entry(
+    input: license_catalog::LicenseCatalogInput,
+) -> Result<license_catalog::LicenseCatalogEntry, String> {
+    ic_cdk::println!("CALL: add_license_catalog_entry for: {}", input.collection_id);
+    is_controller()?;
+    license_catalog::add_entry(input)
+}
+
+#[ic_cdk::update]
+fn update_license_catalog_entry(
+    input: license_catalog::LicenseCatalogInput,
+) -> Result<license_catalog::LicenseCatalogEntry, String> {
+    ic_cdk::println!("CALL: update_license_catalog_entry for: {}", input.collection_id);
+    is_controller()?;
+    license_catalog::update_entry(input)
+}
+
+#[ic_cdk::update]
+fn remove_license_catalog_entry(collection_id: Principal) -> Result<license_catalog::LicenseCatalogEntry, String> {
+    ic_cdk::println!("CALL: remove_license_catalog_entry for: {}", collection_id);
+    is_controller()?;
+    license_catalog::remove_entry(&collection_id)
+}
+
+#[ic_cdk::update]
+fn set_license_on_sale(collection_id: Principal, on_sale: bool) -> Result<license_catalog::LicenseCatalogEntry, String> {
+    ic_cdk::println!("CALL: set_license_on_sale for: {}, on_sale: {}", collection_id, on_sale);
+    is_controller()?;
+    license_catalog::set_on_sale(&collection_id, on_sale)
+}
+
+/// Imports collections still configured under `nft_<id>` keys into the catalog
+#[ic_cdk::update]
+fn migrate_legacy_license_collections() -> Result<u32, String> {
+    ic_cdk::println!("CALL: migrate_legacy_license_collections");
+    is_controller()?;
+    Ok(license_catalog::migrate_legacy_collections())
+}
+
+/// Recomputes the caller's entitlements from the licenses they hold, at most once per
+/// `entitlements_refresh_min_secs`
+#[ic_cdk::update]
+async fn get_my_entitlements() -> Result<entitlements::Entitlements, String> {
+    let caller = ic_cdk::caller();
+    ic_cdk::println!("CALL: get_my_entitlements for: {}", caller);
+    if caller == Principal::anonymous() {
+        return Err("Anonymous callers have no entitlements".to_string());
+    }
+    if let Some(fresh) = entitlements::get_fresh(&caller) {
+        return Ok(fresh);
+    }
+    entitlements::throttle_refresh(&caller)?;
+    Ok(entitlements::refresh_entitlements(caller, false).await)
+}
+
+/// Cached entitlements of any principal
+#[ic_cdk::query]
+fn get_entitlements(principal: Principal) -> Option<entitlements::Entitlements> {
+    ic_cdk::println!("CALL: get_entitlements for: {}", principal);
+    entitlements::get_cached(&principal)
+}
+
+/// Tells other canisters whether `principal` holds an entitlement
+#[ic_cdk::query]
+fn has_entitlement(principal: Principal, name: String) -> bool {
+    ic_cdk::println!("CALL: has_entitlement for: {}, name: {}", principal, name);
+    entitlements::has_entitlement(&principal, &name)
+}
+
+/// Lists one of the caller's license tokens for resale
+#[ic_cdk::update]
+async fn list_license_for_sale(collection_id: Principal, token_id: u128, price: u64) -> Result<license_market::Listing, String> {
+    let caller = ic_cdk::caller();
+    ic_cdk::println!("CALL: list_license_for_sale {}:{} by: {}, price: {}", collection_id, token_id, caller, price);
+    feature_flags::require_feature(FEATURE_LICENSE_MARKET, &caller, true)?;
+    license_market::create_listing(caller, collection_id, token_id, price).await
+}
+
+#[ic_cdk::update]
+fn cancel_license_listing(listing_id: u64) -> Result<license_market::Listing, String> {
+    let caller = ic_cdk::caller();
+    ic_cdk::println!("CALL: cancel_license_listing {} by: {}", listing_id, caller);
+    license_market::cancel_listing(caller, listing_id, is_controller().is_ok())
+}
+
+/// Buys a listed license through the caller's ICRC-2 approval
+#[ic_cdk::update]
+async fn buy_license_listing(listing_id: u64) -> Result<license_market::Listing, String> {
+    let caller = ic_cdk::caller();
+    ic_cdk::println!("CALL: buy_license_listing {} by: {}", listing_id, caller);
+    feature_flags::require_feature(FEATURE_LICENSE_MARKET, &caller, true)?;
+    let listing = license_market::buy_listing(caller, listing_id).await?;
+    // The license moved, so both sides' quota and entitlements change
+    storage_quota::refresh_user_quota(caller, true).await;
+    storage_quota::refresh_user_quota(listing.seller, true).await;
+    Ok(listing)
+}
+
+/// Settles a listing left Settling by a lost reply or an interrupted purchase (controller only)
+#[ic_cdk::update]
+async fn reconcile_license_listing(listing_id: u64) -> Result<license_market::Listing, String> {
+    ic_cdk::println!("CALL: reconcile_license_listing {}", listing_id);
+    is_controller()?;
+    let listing = license_market::reconcile_listing(listing_id).await?;
+    if let license_market::ListingStatus::Sold { buyer, .. } = listing.status {
+        storage_quota::refresh_user_quota(buyer, true).await;
+        storage_quota::refresh_user_quota(listing.seller, true).await;
+    }
+    Ok(listing)
+}
+
+/// Active resale listings, newest first
+#[ic_cdk::query]
+fn list_license_listings(
+    collection_id: Option<Principal>,
+    page: Option<u32>,
+    page_size: Option<u32>,
+) -> Vec<license_market::Listing> {
+    ic_cdk::println!("CALL: list_license_listings for collection: {:?}", collection_id);
+    let page = page.unwrap_or(0) as usize;
+    let page_size = page_size.unwrap_or(50).min(200) as usize;
+    license_market::list_active(collection_id, page * page_size, page_size)
+}
+
+#[ic_cdk::query]
+fn get_license_listing(listing_id: u64) -> Option<license_market::Listing> {
+    ic_cdk::println!("CALL: get_license_listing {}", listing_id);
+    license_market::get_listing(listing_id)
+}
+
+/// The caller's listings in any status, newest first
+#[ic_cdk::query]
+fn get_my_license_listings() -> Vec<license_market::Listing> {
+    let caller = ic_cdk::caller();
+    ic_cdk::println!("CALL: get_my_license_listings for: {}", caller);
+    license_market::list_by_seller(caller)
+}
+
+/// Licenses bought by the caller, newest first
+#[ic_cdk::query]
+fn get_my_licenses(
+    filter: Option<license_records::LicenseStatusFilter>,
+    collection_id: Option<String>,
+) -> Vec<UserLicenseRecord> {
+    let caller = ic_cdk::caller();
+    ic_cdk::println!("CALL: get_my_licenses for: {}, filter: {:?}", caller, filter);
+    license_records::list_by_owner(
+        &caller.to_text(),
+        filter.unwrap_or(license_records::LicenseStatusFilter::All),
+        collection_id.as_deref(),
+    )
+}
+
+/// Renews one of the caller's licenses, charging the renewal price through ICRC-2
+#[ic_cdk::update]
+async fn renew_license(license_id: u64) -> Result<UserLicenseRecord, String> {
+    let caller = ic_cdk::caller();
+    ic_cdk::println!("CALL: renew_license {} for: {}", license_id, caller);
+    is_called_by_dapp_frontend()?;
+    let record = license_types::renew_license(caller, license_id).await?;
+    storage_quota::refresh_user_quota(caller, true).await;
+    Ok(record)
+}
+
+/// Tells other canisters whether `principal` holds a currently valid license of a collection.
+/// An update call, since token ownership is read from the collection canister.
+#[ic_cdk::update]
+async fn check_license(principal: Principal, collection_id: String) -> Result<license_records::LicenseCheck, String> {
+    ic_cdk::println!("CALL: check_license for: {}, collection: {}", principal, collection_id);
+    license_types::check_license(principal, &collection_id).await
+}
+
+/// Flags lapsed licenses now instead of waiting for the timer
+#[ic_cdk::update]
+async fn enforce_license_expiry() -> Result<u64, String> {
+    ic_cdk::println!("CALL: enforce_license_expiry");
+    is_controller()?;
+    Ok(license_types::enforce_license_expiry().await)
+}
+
+/// Exports license sales joined with their payments, in sale order
+#[ic_cdk::query]
+fn export_license_sales(
+    collection_id: Option<String>,
+    page: Option<u32>,
+    page_size: Option<u32>,
+) -> Result<Vec<license_records::LicenseSale>, String> {
+    ic_cdk::println!("CALL: export_license_sales for collection: {:?}", collection_id);
+    is_controller()?;
+    let page = page.unwrap_or(0) as usize;
+    let page_size = page_size.unwrap_or(100).min(500) as usize;
+    Ok(license_records::export_sales(collection_id.as_deref(), page * page_size, page_size))
+}
+
+/// Re-reads the tokens held by a collection's sale account into the sellable inventory
+#[ic_cdk::update]
+async fn sync_license_inventory(collection_id: Principal) -> Result<license_inventory::InventorySummary, String> {
+    ic_cdk::println!("CALL: sync_license_inventory for collection: {}", collection_id);
+    is_controller()?;
+    license_inventory::sync_inventory(collection_id).await
+}
+
+#[ic_cdk::query]
+fn get_license_inventory(collection_id: Principal) -> Result<license_inventory::InventorySummary, String> {
+    ic_cdk::println!("CALL: get_license_inventory for collection: {}", collection_id);
+    license_inventory::get_summary(collection_id)
+}
+
+/// Lists license purchases of `buyer` (defaults to the caller). Only controllers may see other buyers.
+#[ic_cdk::query]
+fn list_license_purchases(buyer: Option<Principal>) -> Result<Vec<license_purchases::PurchaseRecord>, String> {
+    ic_cdk::println!("CALL: list_license_purchases for buyer: {:?}", buyer);
+    let caller = ic_cdk::caller();
+    let buyer = buyer.unwrap_or(caller);
+    if buyer != caller {
+        is_controller()?;
+    }
+    Ok(license_purchases::list_purchases(Some(buyer)))
+}
+
+#[ic_cdk::query]
+fn get_license_purchase(id: u64) -> Result<Option<license_purchases::PurchaseRecord>, String> {
+    ic_cdk::println!("CALL: get_license_purchase with id: {}", id);
+    let purchase = license_purchases::get_purchase(id);
+    if purchase.as_ref().map_or(false, |p| p.buyer != ic_cdk::caller()) {
+        is_controller()?;
+    }
+    Ok(purchase)
+}
+
+/// Settles a purchase left pending by a lost payment or transfer reply (controller only)
+#[ic_cdk::update]
+async fn reconcile_license_purchase(id: u64) -> Result<license_purchases::PurchaseRecord, String> {
+    ic_cdk::println!("CALL: reconcile_license_purchase with id: {}", id);
+    is_controller()?;
+    license_types::reconcile_purchase(id).await
+}
+
 #[ic_cdk::update]
 fn claim_reward(dapp_principal: Option<String>, wallet_principal: Option<String>, quest_id: u64) -> bool {
     ic_cdk::println!("CALL: claim_reward for dapp: {:?}, wallet: {:?}, quest: {}", dapp_principal, wallet_principal, quest_id);
@@ -269,18 +748,33 @@ fn claim_reward(dapp_principal: Option<String>, wallet_principal: Option<String>
     buss_types::claim_reward(dapp_principal, wallet_principal, quest_id)
 }
 
+/// Attaches ic-oss policies and records the grant; `expires_at` is a nanosecond timestamp
 #[ic_cdk::update]
-async fn attach_policies(bucket_id: String, cluster_id: String, principal_id: String, policies: String) -> Result<(), String> {
-    ic_cdk::println!("CALL: attach_policies for bucket: {}, cluster: {}, principal: {}", bucket_id, cluster_id, principal_id);
-    is_called_by_dapp_frontend()?;
-    ic_oss_dapp::attach_policies(bucket_id, cluster_id, principal_id, policies).await
+async fn attach_policies(bucket_id: String, cluster_id: String, principal_id: String, policies: String, expires_at: Option<u64>) -> Result<(), String> {
+    ic_cdk::println!("CALL: attach_policies for bucket: {}, cluster: {}, principal: {}, expires_at: {:?}", bucket_id, cluster_id, principal_id, expires_at);
+    is_controller()?;
+    policy_grants::attach_policies(bucket_id, cluster_id, principal_id, policies, expires_at)
+        .await
+        .map(|_| ())
 }
 
 #[ic_cdk::update]
 async fn detach_policies(bucket_id: String, cluster_id: String, principal_id: String, policies: String) -> Result<(), String> {
     ic_cdk::println!("CALL: detach_policies for bucket: {}, cluster: {}, principal: {}", bucket_id, cluster_id, principal_id);
-    is_called_by_dapp_frontend()?;
-    ic_oss_dapp::detach_policies(bucket_id, cluster_id, principal_id, policies).await
+    is_controller()?;
+    policy_grants::detach_policies(bucket_id, cluster_id, principal_id, policies).await
+}
+
+#[ic_cdk::query]
+fn list_policy_grants_by_subject(subject: Principal, include_revoked: Option<bool>) -> Vec<policy_grants::PolicyGrant> {
+    ic_cdk::println!("CALL: list_policy_grants_by_subject for subject: {}", subject);
+    policy_grants::list_grants_by_subject(subject, include_revoked.unwrap_or(false))
+}
+
+#[ic_cdk::query]
+fn list_policy_grants_by_bucket(bucket_id: Principal, include_revoked: Option<bool>) -> Vec<policy_grants::PolicyGrant> {
+    ic_cdk::println!("CALL: list_policy_grants_by_bucket for bucket: {}", bucket_id);
+    policy_grants::list_grants_by_bucket(bucket_id, include_revoked.unwrap_or(false))
 }
 
 #[ic_cdk::query]
@@ -306,6 +800,23 @@ async fn get_access_token(wallet_principal: String) -> Result<ic_oss_dapp::Acces
     }
 }
 
+/// Issues a listener a read-only token on the owner's published voice files: `asset_id` when
+/// given and public or unlisted, otherwise the owner's public files
+#[ic_cdk::update]
+async fn get_read_access_token(wallet_principal: String, owner_principal: String, asset_id: Option<u64>) -> Result<ic_oss_dapp::AccessTokenResponse, String> {
+    ic_cdk::println!("CALL: get_read_access_token for wallet: {}, owner: {}, asset: {:?}", wallet_principal, owner_principal, asset_id);
+    is_called_by_dapp_frontend()?;
+    ic_oss_dapp::get_scoped_access_token(wallet_principal, ic_oss_dapp::TokenScope::ReadOnly, Some(owner_principal), asset_id).await
+}
+
+/// Returns a token for the given scope, reusing the cached one while it is still valid
+#[ic_cdk::update]
+async fn refresh_access_token(wallet_principal: String, scope: ic_oss_dapp::TokenScope, owner_principal: Option<String>, asset_id: Option<u64>) -> Result<ic_oss_dapp::AccessTokenResponse, String> {
+    ic_cdk::println!("CALL: refresh_access_token for wallet: {}, scope: {:?}, owner: {:?}, asset: {:?}", wallet_principal, scope, owner_principal, asset_id);
+    is_called_by_dapp_frontend()?;
+    ic_oss_dapp::get_scoped_access_token(wallet_principal, scope, owner_principal, asset_id).await
+}
+
 //todo::Update calls consume significantly more cycles than query call
 #[ic_cdk::update]
 fn get_user_tasks(principal_id: String) -> Option<Vec<buss_types::TaskData>> {
@@ -420,6 +931,63 @@ fn get_friend_infos(owner_principal: String) -> Vec<(buss_types::CustomInfo, can
     activate_types::get_friend_infos(owner_principal)
 }
 
+// Canister registry
+#[ic_cdk::update]
+async fn set_canister(role: CanisterRole, canister_id: Principal) -> Result<(), String> {
+    ic_cdk::println!("CALL: set_canister for role: {:?} to {}", role, canister_id);
+    is_controller()?;
+    canister_registry::set_canister(role, canister_id)
+}
+
+#[ic_cdk::update]
+async fn remove_canister(role: CanisterRole) -> Result<(), String> {
+    ic_cdk::println!("CALL: remove_canister for role: {:?}", role);
+    is_controller()?;
+    canister_registry::remove_canister(role)
+}
+
+#[ic_cdk::query]
+fn get_canister(role: CanisterRole) -> Option<Principal> {
+    ic_cdk::println!("CALL: get_canister for role: {:?}", role);
+    canister_registry::get_canister(role)
+}
+
+#[ic_cdk::query]
+fn list_canisters() -> Vec<canister_registry::RegisteredCanister> {
+    ic_cdk::println!("CALL: list_canisters");
+    canister_registry::list_canisters()
+}
+
+#[ic_cdk::query]
+fn get_canister_history(role: Option<CanisterRole>) -> Result<Vec<canister_registry::CanisterRoleChange>, String> {
+    ic_cdk::println!("CALL: get_canister_history for role: {:?}", role);
+    is_controller()?;
+    Ok(canister_registry::list_history(role))
+}
+
+/// Pings every registered canister now and returns the fresh report
+#[ic_cdk::update]
+async fn check_system_health() -> Result<health::SystemHealth, String> {
+    ic_cdk::println!("CALL: check_system_health");
+    is_controller()?;
+    Ok(health::check_dependencies().await)
+}
+
+#[ic_cdk::query]
+fn get_system_health() -> health::SystemHealth {
+    ic_cdk::println!("CALL: get_system_health");
+    health::get_system_health()
+}
+
+// Text-based helpers behind the legacy per-role set_/get_ endpoints
+fn set_canister_by_text(role: CanisterRole, canister_id: String) -> Result<(), String> {
+    canister_registry::set_canister(role, canister_registry::parse_canister_id(&canister_id)?)
+}
+
+fn get_canister_text(role: CanisterRole) -> Option<String> {
+    canister_registry::get_canister(role).map(|id| id.to_text())
+}
+
 // Canister mapping functions
 #[ic_cdk::update]
 async fn add_canister_mapping(key: String, canister_id: String) -> Result<(), String> {
@@ -452,13 +1020,13 @@ async fn initialize_default_canisters() -> Result<(), String> {
 async fn set_frontend_canister(canister_id: String) -> Result<(), String> {
     ic_cdk::println!("CALL: set_frontend_canister to {}", canister_id);
     is_controller()?;
-    buss_types::set_frontend_canister(canister_id)
+    set_canister_by_text(CanisterRole::Frontend, canister_id)
 }
 
 #[ic_cdk::query]
 fn get_frontend_canister() -> Option<String> {
     ic_cdk::println!("CALL: get_frontend_canister");
-    buss_types::get_frontend_canister()
+    get_canister_text(CanisterRole::Frontend)
 }
 
 // Bulklet canister specific functions
@@ -466,13 +1034,35 @@ fn get_frontend_canister() -> Option<String> {
 async fn set_bucket_canister(canister_id: String) -> Result<(), String> {
     ic_cdk::println!("CALL: set_bulklet_canister to {}", canister_id);
     is_controller()?;
-    buss_types::set_bulklet_canister(canister_id)
+    set_canister_by_text(CanisterRole::Bucket, canister_id)
 }
 
 #[ic_cdk::query]
 fn get_bucket_canister() -> Option<String> {
     ic_cdk::println!("CALL: get_bulklet_canister");
-    buss_types::get_bulklet_canister()
+    get_canister_text(CanisterRole::Bucket)
+}
+
+/// Adds or updates a bucket in the pool used to place new users' folders
+#[ic_cdk::update]
+async fn add_bucket_to_pool(key: String, canister_id: String, weight: Option<u32>, accepting_uploads: Option<bool>) -> Result<(), String> {
+    ic_cdk::println!("CALL: add_bucket_to_pool for key: {}, canister: {}, weight: {:?}", key, canister_id, weight);
+    is_controller()?;
+    buss_types::add_bucket_to_pool(key, canister_id, weight, accepting_uploads)
+}
+
+/// Marks whether a bucket still takes new users, e.g. when it nears capacity
+#[ic_cdk::update]
+async fn set_bucket_accepting_uploads(key: String, accepting_uploads: bool) -> Result<(), String> {
+    ic_cdk::println!("CALL: set_bucket_accepting_uploads for key: {} to {}", key, accepting_uploads);
+    is_controller()?;
+    buss_types::set_bucket_accepting_uploads(key, accepting_uploads)
+}
+
+#[ic_cdk::query]
+fn get_bucket_pool() -> Vec<buss_types::CanisterMapping> {
+    ic_cdk::println!("CALL: get_bucket_pool");
+    buss_types::get_bucket_pool()
 }
 
 // Cluster canister specific functions
@@ -480,28 +1070,35 @@ fn get_bucket_canister() -> Option<String> {
 async fn set_cluster_canister(canister_id: String) -> Result<(), String> {
     ic_cdk::println!("CALL: set_cluster_canister to {}", canister_id);
     is_controller()?;
-    buss_types::set_cluster_canister(canister_id)
+    set_canister_by_text(CanisterRole::Cluster, canister_id)
 }
 
 #[ic_cdk::query]
 fn get_cluster_canister() -> Option<String> {
     ic_cdk::println!("CALL: get_cluster_canister");
-    buss_types::get_cluster_canister()
+    get_canister_text(CanisterRole::Cluster)
 }
 
 
+/// Returns the ic-oss folder provisioned for a principal
+#[ic_cdk::query]
+fn get_user_folder(principal_id: Principal) -> Option<ic_oss_dapp::UserFolder> {
+    ic_cdk::println!("CALL: get_user_folder for principal: {}", principal_id);
+    ic_oss_dapp::get_user_folder(&principal_id)
+}
+
 // Mugc canister specific functions
 #[ic_cdk::update]
 async fn set_mugc_canister(canister_id: String) -> Result<(), String> {
     ic_cdk::println!("CALL: set_mugc_canister to {}", canister_id);
     is_controller()?;
-    buss_types::set_mugc_canister(canister_id)
+    set_canister_by_text(CanisterRole::Mugc, canister_id)
 }
 
 #[ic_cdk::query]
 fn get_mugc_canister() -> Option<String> {
     ic_cdk::println!("CALL: get_mugc_canister");
-    buss_types::get_mugc_canister()
+    get_canister_text(CanisterRole::Mugc)
 }
 
 // VMC canister specific functions
@@ -509,42 +1106,81 @@ fn get_mugc_canister() -> Option<String> {
 async fn set_vmc_canister(canister_id: String) -> Result<(), String> {
     ic_cdk::println!("CALL: set_vmc_canister to {}", canister_id);
     is_controller()?;
-    buss_types::set_vmc_canister(canister_id)
+    set_canister_by_text(CanisterRole::Vmc, canister_id)
 }
 
 #[ic_cdk::query]
 fn get_vmc_canister() -> Option<String> {
     ic_cdk::println!("CALL: get_vmc_canister");
-    buss_types::get_vmc_canister()
+    get_canister_text(CanisterRole::Vmc)
 }
 
-/// Records a voice file in the ledger
+/// Records a voice file in the ledger and returns its asset id
 #[ic_cdk::update]
 #[candid::candid_method(update)]
 async fn upload_voice_file(
     principal_id: Principal,
     folder_id: String,
     file_id: String,
-    _content: Vec<u8>,
+    content: Vec<u8>,
     custom: Option<Vec<(String, String)>>,
-) -> Result<(), String> {
+    metadata: Option<VoiceFileMetadata>,
+) -> Result<u64, String> {
     ic_cdk::println!("CALL: upload_voice_file for principal: {}, folder: {}, file: {}", principal_id, folder_id, file_id);
     is_called_by_dapp_frontend()?;
+    // Files are recorded against the owner's folder and quota, so only the owner may record them
+    if principal_id != ic_cdk::caller() {
+        is_controller().map_err(|_| "Voice files can only be recorded for the caller's own principal".to_string())?;
+    }
     let now = time();
+
+    // Parse folder_id and file_id from string to u32
+    let folder_id = folder_id.trim_end_matches('/').parse::<u32>()
+        .map_err(|_| "Invalid folder ID format".to_string())?;
+
+    // Files must live in the folder provisioned for the owner
+    let user_folder = ic_oss_dapp::get_user_folder(&principal_id)
+        .ok_or("No folder provisioned for principal, request an access token first")?;
+    if user_folder.folder_id != folder_id {
+        return Err(format!("Folder {} does not belong to principal {}", folder_id, principal_id));
+    }
+    let file_id = file_id.parse::<u32>()
+        .map_err(|_| "Invalid file ID format".to_string())?;
+
+    // Validate typed metadata against the configured limits, then against the stored file
+    let limits = VoiceUploadLimits::load()?;
+    let voice_meta = validate_voice_metadata(metadata, &content, &limits)?;
+    if let Some(custom) = &custom {
+        voice_oss_type::validate_custom_metadata(custom)?;
+    }
+    let stored = ic_oss_dapp::get_bucket_file_info(user_folder.bucket_id, file_id).await?;
+    let voice_meta = voice_oss_type::verify_stored_file(voice_meta, folder_id, &stored, &limits)?;
+
+    // Detect re-uploads of the same recording by the same owner
+    if let Some(existing) = check_voice_upload_duplicate(principal_id, &voice_meta)? {
+        discard_duplicate_file(user_folder.bucket_id, file_id, existing).await;
+        return Ok(existing);
+    }
+
+    // Enforce the per-user quota, raised by any licenses the owner holds
+    let file_size = voice_meta.size.ok_or("Voice file size is unknown")?;
+    storage_quota::refresh_user_quota(principal_id, false).await;
+
+    // A concurrent upload of the same content may have been stored while awaiting; nothing
+    // below awaits, so this check and the store are atomic
+    if let Some(existing) = check_voice_upload_duplicate(principal_id, &voice_meta)? {
+        discard_duplicate_file(user_folder.bucket_id, file_id, existing).await;
+        return Ok(existing);
+    }
+    storage_quota::reserve_storage(principal_id, file_size)?;
     
     // Convert custom metadata to proper format
-    let metadata = custom.map(|items| {
+    let custom_metadata = custom.map(|items| {
         items.into_iter()
             .map(|(k, v)| (k, MetadataValue::Text(v)))
             .collect()
     });
     
-    // Parse folder_id and file_id from string to u32
-    let folder_id = folder_id.parse::<u32>()
-        .map_err(|_| "Invalid folder ID format".to_string())?;
-    let file_id = file_id.parse::<u32>()
-        .map_err(|_| "Invalid file ID format".to_string())?;
-    
     let data = VoiceAssetData {
         principal_id,
         folder_id,
@@ -552,12 +1188,69 @@ async fn upload_voice_file(
         status: 0, // Active
         created_at: now,
         updated_at: Some(now),
-        custom: metadata,
+        custom: custom_metadata,
+        duration_ms: voice_meta.duration_ms,
+        mime_type: voice_meta.mime_type,
+        codec: voice_meta.codec,
+        sample_rate: voice_meta.sample_rate,
+        channels: voice_meta.channels,
+        size: voice_meta.size,
+        content_hash: voice_meta.content_hash,
+        trashed_at: None,
+        bucket_id: Some(user_folder.bucket_id),
     };
 
     store_voice_asset_data(data)
-        .map(|_| ())
-        .map_err(|e| format!("Failed to store voice asset data: {}", e))
+        .map_err(|e| {
+            storage_quota::release_storage(principal_id, file_size);
+            format!("Failed to store voice asset data: {}", e)
+        })
+}
+
+// Applies the dedup policy: Ok(Some(id)) returns an existing asset, Ok(None) stores the upload
+fn check_voice_upload_duplicate(principal_id: Principal, voice_meta: &VoiceFileMetadata) -> Result<Option<u64>, String> {
+    let existing = match voice_meta.content_hash.as_ref().and_then(|hash| find_duplicate_asset(principal_id, hash)) {
+        Some(existing) => existing,
+        None => return Ok(None),
+    };
+    match DedupPolicy::load() {
+        DedupPolicy::Reject => Err(format!("Duplicate voice file, existing asset id: {}", existing)),
+        DedupPolicy::ReturnExisting => {
+            ic_cdk::println!("Duplicate voice file for {}, returning existing asset {}", principal_id, existing);
+            Ok(Some(existing))
+        }
+        DedupPolicy::Allow => Ok(None),
+    }
+}
+
+// Deletes the bucket copy of an upload that resolved to an existing asset, so only the existing
+// file is kept. A retried upload of the existing asset's own file is left alone.
+async fn discard_duplicate_file(bucket: Principal, file_id: u32, existing: u64) {
+    let own_file = get_voice_asset_data(existing)
+        .map_or(false, |data| data.file_id == file_id && data.bucket_id.map_or(true, |b| b == bucket));
+    if own_file {
+        return;
+    }
+    if let Err(e) = ic_oss_dapp::delete_bucket_file(bucket, file_id).await {
+        ic_cdk::println!("Failed to delete duplicate voice file {} from {}: {}", file_id, bucket, e);
+    }
+}
+
+/// Returns the id of an active asset of the caller with the same content hash, if any
+#[ic_cdk::query]
+fn check_voice_duplicate(content_hash: String) -> Option<u64> {
+    let caller = ic_cdk::caller();
+    ic_cdk::println!("CALL: check_voice_duplicate for principal: {}, hash: {}", caller, content_hash);
+    find_duplicate_asset(caller, &content_hash)
+}
+
+// Voice files may only be changed by their owner or a controller
+fn authorize_voice_owner(index: u64) -> Result<(), String> {
+    let data = get_voice_asset_data(index).ok_or("Voice asset not found")?;
+    if data.principal_id != ic_cdk::caller() {
+        is_controller().map_err(|_| "Only the owner can change a voice file".to_string())?;
+    }
+    Ok(())
 }
 
 /// Marks a voice file as deleted in the ledger
@@ -570,11 +1263,121 @@ async fn delete_voice_file(file_id: String) -> Result<(), String> {
     // Parse file_id from string to u64
     let index = file_id.parse::<u64>()
         .map_err(|_| "Invalid file ID format".to_string())?;
+    authorize_voice_owner(index)?;
     
     delete_voice_asset_data(index)
         .map_err(|e| format!("Failed to delete voice asset data: {}", e))
 }
 
+/// Sets the title, description, tags, cover and visibility of one of the caller's voice files
+#[ic_cdk::update]
+fn publish_voice(asset_id: u64, request: voice_catalog::PublishVoiceRequest) -> Result<voice_catalog::VoicePublication, String> {
+    let caller = ic_cdk::caller();
+    ic_cdk::println!("CALL: publish_voice {} by: {}, visibility: {:?}", asset_id, caller, request.visibility);
+    voice_catalog::publish_voice(caller, asset_id, request)
+}
+
+/// Public voice catalog, newest or trending first, optionally filtered by tag
+#[ic_cdk::query]
+fn list_public_voices(query: Option<voice_catalog::PublicVoiceQuery>) -> voice_catalog::PublicVoicePage {
+    ic_cdk::println!("CALL: list_public_voices");
+    voice_catalog::list_public(query.unwrap_or_default())
+}
+
+/// A public or unlisted voice asset by id
+#[ic_cdk::query]
+fn get_public_voice(asset_id: u64) -> Option<voice_catalog::PublicVoice> {
+    ic_cdk::println!("CALL: get_public_voice {}", asset_id);
+    voice_catalog::get_public_voice(asset_id)
+}
+
+/// Counts a play of a published voice asset; repeat plays within the dedup window are ignored
+#[ic_cdk::update]
+fn record_voice_play(asset_id: u64) -> Result<voice_engagement::VoiceEngagement, String> {
+    let caller = ic_cdk::caller();
+    ic_cdk::println!("CALL: record_voice_play {} by: {}", asset_id, caller);
+    voice_engagement::record_play(caller, asset_id)
+}
+
+/// Likes or unlikes a published voice asset
+#[ic_cdk::update]
+fn like_voice(asset_id: u64, liked: bool) -> Result<voice_engagement::VoiceEngagement, String> {
+    let caller = ic_cdk::caller();
+    ic_cdk::println!("CALL: like_voice {} by: {}, liked: {}", asset_id, caller, liked);
+    voice_engagement::set_like(caller, asset_id, liked)
+}
+
+#[ic_cdk::update]
+fn record_voice_share(asset_id: u64) -> Result<voice_engagement::VoiceEngagement, String> {
+    let caller = ic_cdk::caller();
+    ic_cdk::println!("CALL: record_voice_share {} by: {}", asset_id, caller);
+    voice_engagement::record_share(caller, asset_id)
+}
+
+/// Play, listener, like and share counters for a batch of voice assets
+#[ic_cdk::query]
+fn get_voice_engagement(asset_ids: Vec<u64>) -> Result<Vec<voice_engagement::VoiceEngagement>, String> {
+    ic_cdk::println!("CALL: get_voice_engagement for {} assets", asset_ids.len());
+    voice_engagement::get_engagements(asset_ids)
+}
+
+/// Whether the caller liked and when they last played each of a batch of voice assets
+#[ic_cdk::query]
+fn get_my_voice_engagement(asset_ids: Vec<u64>) -> Result<Vec<voice_engagement::MyVoiceEngagement>, String> {
+    let caller = ic_cdk::caller();
+    ic_cdk::println!("CALL: get_my_voice_engagement by: {}", caller);
+    voice_engagement::get_my_engagement(&caller, asset_ids)
+}
+
+/// Restores a voice file from the trash
+#[ic_cdk::update]
+async fn restore_voice_file(file_id: String) -> Result<(), String> {
+    ic_cdk::println!("CALL: restore_voice_file with ID: {}", file_id);
+    is_called_by_dapp_frontend()?;
+
+    let index = file_id.parse::<u64>()
+        .map_err(|_| "Invalid file ID format".to_string())?;
+    authorize_voice_owner(index)?;
+
+    restore_voice_asset_data(index)
+        .map_err(|e| format!("Failed to restore voice asset data: {}", e))
+}
+
+/// Permanently removes a trashed voice file and its ic-oss file
+#[ic_cdk::update]
+async fn purge_voice_file(file_id: String) -> Result<(), String> {
+    ic_cdk::println!("CALL: purge_voice_file with ID: {}", file_id);
+    is_called_by_dapp_frontend()?;
+
+    let index = file_id.parse::<u64>()
+        .map_err(|_| "Invalid file ID format".to_string())?;
+    authorize_voice_owner(index)?;
+
+    voice_oss_type::purge_voice_asset(index)
+        .await
+        .map_err(|e| format!("Failed to purge voice asset data: {}", e))
+}
+
+/// Lists the caller's trashed voice files, newest first; controllers may list anyone's
+#[ic_cdk::query]
+fn list_voice_trash(principal_id: Principal, page: Option<u32>, page_size: Option<u32>) -> Result<Vec<VoiceOssInfo>, String> {
+    ic_cdk::println!("CALL: list_voice_trash for principal: {}, page: {:?}, page_size: {:?}", principal_id, page, page_size);
+    if ic_cdk::caller() != principal_id {
+        is_controller()?;
+    }
+    let page = page.unwrap_or(1).max(1);
+    let size = page_size.unwrap_or(10);
+    Ok(voice_oss_type::list_voice_trash(principal_id, (page as u64 - 1) * size as u64, size))
+}
+
+/// Purges all trashed voice files past their retention period
+#[ic_cdk::update]
+async fn purge_expired_voice_trash() -> Result<u64, String> {
+    ic_cdk::println!("CALL: purge_expired_voice_trash");
+    is_controller()?;
+    Ok(voice_oss_type::purge_expired_trash().await)
+}
+
 /// Lists voice files with optional filtering
 #[ic_cdk::query]
 #[candid::candid_method(query)]
@@ -583,6 +1386,7 @@ fn list_voice_files(
     folder_id: Option<String>,
     _page: Option<u32>,
     page_size: Option<u32>,
+    filter: Option<VoiceFileFilter>,
 ) -> Vec<VoiceOssInfo> {
     ic_cdk::println!("CALL: list_voice_files for principal: {:?}, folder: {:?}, page: {:?}, page_size: {:?}", 
                      principal_id, folder_id, _page, page_size);
@@ -604,6 +1408,7 @@ fn list_voice_files(
         folder_id: folder_id.and_then(|f| f.parse::<u32>().ok()),
         prev,
         take: Some(size),
+        filter,
     };
     
     ic_cdk::println!("Pagination params: page={:?}, size={:?}, prev={:?}", page, size, prev);
@@ -621,6 +1426,24 @@ fn list_voice_files(
     result.unwrap_or_default()
 }
 
+/// Returns storage usage of a principal against its quota
+#[ic_cdk::query]
+fn get_storage_usage(principal_id: Principal) -> storage_quota::StorageUsage {
+    ic_cdk::println!("CALL: get_storage_usage for principal: {}", principal_id);
+    storage_quota::get_storage_usage(principal_id)
+}
+
+/// Recomputes the license based storage quota of the caller; controllers may refresh anyone's
+#[ic_cdk::update]
+async fn refresh_storage_quota(principal_id: Principal) -> Result<storage_quota::StorageUsage, String> {
+    ic_cdk::println!("CALL: refresh_storage_quota for principal: {}", principal_id);
+    if ic_cdk::caller() != principal_id {
+        is_controller()?;
+    }
+    storage_quota::refresh_user_quota(principal_id, true).await;
+    Ok(storage_quota::get_storage_usage(principal_id))
+}
+
 /// Gets voice file details by ID
 #[ic_cdk::query]
 fn get_voice_file(id: u64) -> Option<VoiceAssetData> {
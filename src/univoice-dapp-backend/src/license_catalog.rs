@@ -0,0 +1,240 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
+use std::cell::RefCell;
+
+use crate::buss_types;
+use crate::config_types::{
+    get_optional_config_nat, get_optional_config_principal, get_config_duration_secs, parse_principal,
+};
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const LICENSE_CATALOG_ENTRY_MAX_SIZE: u32 = 4096;
+// Keeps an entry with the longest ids, name and feature list well inside LICENSE_CATALOG_ENTRY_MAX_SIZE
+const LICENSE_ID_MAX_LEN: usize = 64;
+const LICENSE_DISPLAY_NAME_MAX_LEN: usize = 100;
+const LICENSE_FEATURES_MAX: usize = 32;
+const LICENSE_FEATURE_MAX_LEN: usize = 64;
+
+/// Fields of a catalog entry a controller sets
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct LicenseCatalogInput {
+    pub collection_id: Principal,
+    // Short id clients use in place of the principal, formerly the `<id>` of `nft_<id>`
+    pub license_id: String,
+    pub display_name: String,
+    pub tier: u32,
+    pub price: u64,
+    pub payment_ledger: Option<Principal>,
+    // Falls back to `price` when unset
+    pub renewal_price: Option<u64>,
+    // None means licenses of this collection never expire
+    pub duration_secs: Option<u64>,
+    pub features: Vec<String>,
+    pub on_sale: bool,
+}
+
+/// A sellable license collection
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct LicenseCatalogEntry {
+    pub collection_id: Principal,
+    pub license_id: String,
+    pub display_name: String,
+    pub tier: u32,
+    pub price: u64,
+    pub payment_ledger: Option<Principal>,
+    pub renewal_price: Option<u64>,
+    pub duration_secs: Option<u64>,
+    pub features: Vec<String>,
+    pub on_sale: bool,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub updated_by: Principal,
+}
+
+impl Storable for LicenseCatalogEntry {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize LicenseCatalogEntry");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize LicenseCatalogEntry")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: LICENSE_CATALOG_ENTRY_MAX_SIZE,
+        is_fixed_size: false,
+    };
+}
+
+thread_local! {
+    // Keyed by collection principal text
+    static LICENSE_CATALOG: RefCell<StableBTreeMap<String, LicenseCatalogEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26)))
+        )
+    );
+}
+
+fn validate(input: &LicenseCatalogInput) -> Result<(), String> {
+    if input.collection_id == Principal::anonymous() || input.collection_id == Principal::management_canister() {
+        return Err(format!("{} is not a valid collection", input.collection_id));
+    }
+    if input.license_id.trim().is_empty() || input.license_id.contains(char::is_whitespace) {
+        return Err("License id must be non-empty and contain no whitespace".to_string());
+    }
+    if input.license_id.len() > LICENSE_ID_MAX_LEN {
+        return Err(format!("License id exceeds {} bytes", LICENSE_ID_MAX_LEN));
+    }
+    if input.display_name.trim().is_empty() {
+        return Err("Display name must not be empty".to_string());
+    }
+    if input.display_name.len() > LICENSE_DISPLAY_NAME_MAX_LEN {
+        return Err(format!("Display name exceeds {} bytes", LICENSE_DISPLAY_NAME_MAX_LEN));
+    }
+    let paid = input.price > 0 || input.renewal_price.map_or(false, |p| p > 0);
+    if paid && input.payment_ledger.is_none() {
+        return Err("A payment ledger is required for a paid license".to_string());
+    }
+    if input.duration_secs == Some(0) {
+        return Err("Duration must be positive; leave it unset for licenses that never expire".to_string());
+    }
+    if input.features.len() > LICENSE_FEATURES_MAX {
+        return Err(format!("At most {} features are allowed", LICENSE_FEATURES_MAX));
+    }
+    for feature in &input.features {
+        if feature.len() > LICENSE_FEATURE_MAX_LEN {
+            return Err(format!("Feature {} exceeds {} bytes", feature, LICENSE_FEATURE_MAX_LEN));
+        }
+        crate::entitlements::parse_feature(feature)?;
+    }
+    if let Some(other) = find_by_license_id(&input.license_id) {
+        if other.collection_id != input.collection_id {
+            return Err(format!("License id {} is already used by {}", input.license_id, other.collection_id));
+        }
+    }
+    Ok(())
+}
+
+fn store(input: LicenseCatalogInput, created_at: u64) -> LicenseCatalogEntry {
+    let entry = LicenseCatalogEntry {
+        collection_id: input.collection_id,
+        license_id: input.license_id,
+        display_name: input.display_name,
+        tier: input.tier,
+        price: input.price,
+        payment_ledger: input.payment_ledger,
+        renewal_price: input.renewal_price,
+        duration_secs: input.duration_secs,
+        features: input.features,
+        on_sale: input.on_sale,
+        created_at,
+        updated_at: ic_cdk::api::time(),
+        updated_by: ic_cdk::caller(),
+    };
+    LICENSE_CATALOG.with(|catalog| catalog.borrow_mut().insert(entry.collection_id.to_text(), entry.clone()));
+    entry
+}
+
+pub fn add_entry(input: LicenseCatalogInput) -> Result<LicenseCatalogEntry, String> {
+    validate(&input)?;
+    if get_entry(&input.collection_id).is_some() {
+        return Err(format!("Collection {} is already in the catalog", input.collection_id));
+    }
+    Ok(store(input, ic_cdk::api::time()))
+}
+
+pub fn update_entry(input: LicenseCatalogInput) -> Result<LicenseCatalogEntry, String> {
+    validate(&input)?;
+    let existing = get_entry(&input.collection_id)
+        .ok_or(format!("Collection {} is not in the catalog", input.collection_id))?;
+    Ok(store(input, existing.created_at))
+}
+
+pub fn remove_entry(collection_id: &Principal) -> Result<LicenseCatalogEntry, String> {
+    LICENSE_CATALOG.with(|catalog| catalog.borrow_mut().remove(&collection_id.to_text()))
+        .ok_or(format!("Collection {} is not in the catalog", collection_id))
+}
+
+pub fn set_on_sale(collection_id: &Principal, on_sale: bool) -> Result<LicenseCatalogEntry, String> {
+    let mut entry = get_entry(collection_id).ok_or(format!("Collection {} is not in the catalog", collection_id))?;
+    entry.on_sale = on_sale;
+    entry.updated_at = ic_cdk::api::time();
+    entry.updated_by = ic_cdk::caller();
+    LICENSE_CATALOG.with(|catalog| catalog.borrow_mut().insert(collection_id.to_text(), entry.clone()));
+    Ok(entry)
+}
+
+pub fn get_entry(collection_id: &Principal) -> Option<LicenseCatalogEntry> {
+    LICENSE_CATALOG.with(|catalog| catalog.borrow().get(&collection_id.to_text()))
+}
+
+pub fn find_by_license_id(license_id: &str) -> Option<LicenseCatalogEntry> {
+    LICENSE_CATALOG.with(|catalog| {
+        catalog.borrow().iter().map(|(_, e)| e).find(|e| e.license_id == license_id)
+    })
+}
+
+/// Catalog entries ordered by tier, then display name
+pub fn list_entries(include_off_sale: bool) -> Vec<LicenseCatalogEntry> {
+    let mut entries: Vec<LicenseCatalogEntry> = LICENSE_CATALOG.with(|catalog| {
+        catalog.borrow().iter().map(|(_, e)| e).filter(|e| include_off_sale || e.on_sale).collect()
+    });
+    entries.sort_by(|a, b| a.tier.cmp(&b.tier).then_with(|| a.display_name.cmp(&b.display_name)));
+    entries
+}
+
+/// Entry that may be sold right now
+pub fn get_sellable(collection_id: &Principal) -> Result<LicenseCatalogEntry, String> {
+    let entry = get_entry(collection_id).ok_or(format!("Collection {} is not in the license catalog", collection_id))?;
+    if !entry.on_sale {
+        return Err(format!("{} is not on sale", entry.display_name));
+    }
+    Ok(entry)
+}
+
+/// Creates catalog entries for collections still configured under `nft_<id>` keys, taking
+/// price, ledger and duration from the `<collection>_*` keys. Collections without a price
+/// are imported off sale. Existing entries are left alone.
+pub fn migrate_legacy_collections() -> u32 {
+    let mut migrated = 0;
+    for info in buss_types::list_info_by_prefix("nft_", true, 0, usize::MAX) {
+        let license_id = info.key.trim_start_matches("nft_").to_string();
+        let result = parse_principal(&info.content).and_then(|collection_id| {
+            if get_entry(&collection_id).is_some() {
+                return Ok(false);
+            }
+            let collection = collection_id.to_text();
+            let price = get_optional_config_nat(&format!("{}_price", collection))?;
+            let duration_secs = get_config_duration_secs(&format!("{}_expired_duration", collection)).ok();
+            let input = LicenseCatalogInput {
+                collection_id,
+                display_name: license_id.clone(),
+                license_id: license_id.clone(),
+                tier: 0,
+                price: price.unwrap_or(0),
+                payment_ledger: get_optional_config_principal(&format!("{}_payment_ledger", collection))?,
+                renewal_price: get_optional_config_nat(&format!("{}_renewal_price", collection))?,
+                duration_secs,
+                features: Vec::new(),
+                on_sale: price.is_some(),
+            };
+            validate(&input)?;
+            store(input, ic_cdk::api::time());
+            Ok(true)
+        });
+        match result {
+            Ok(true) => migrated += 1,
+            Ok(false) => {}
+            Err(e) => ic_cdk::println!("Skipping legacy license collection {}: {}", info.key, e),
+        }
+    }
+    if migrated > 0 {
+        ic_cdk::println!("Migrated {} license collections into the catalog", migrated);
+    }
+    migrated
+}
@@ -0,0 +1,192 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
+use std::cell::RefCell;
+
+use crate::config_types::get_optional_config_principal;
+use crate::license_types::UserNFTHolding;
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum InventoryStatus {
+    Available,
+    Reserved { purchase_id: u64, at: u64 },
+    Sold { purchase_id: u64, at: u64 },
+}
+
+/// A license token held by a collection's sale account
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct InventoryToken {
+    pub collection_id: Principal,
+    pub token_id: u128,
+    pub status: InventoryStatus,
+    pub synced_at: u64,
+}
+
+impl Storable for InventoryToken {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize InventoryToken");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize InventoryToken")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct InventorySummary {
+    pub collection_id: Principal,
+    pub sale_account: Principal,
+    pub available: u64,
+    pub reserved: u64,
+    pub sold: u64,
+}
+
+thread_local! {
+    // Keyed by "<collection>:<zero-padded token id>" so a collection's tokens are contiguous and ordered
+    static LICENSE_INVENTORY: RefCell<StableBTreeMap<String, InventoryToken, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21)))
+        )
+    );
+}
+
+fn collection_prefix(collection_id: &Principal) -> String {
+    format!("{}:", collection_id.to_text())
+}
+
+fn inventory_key(collection_id: &Principal, token_id: u128) -> String {
+    format!("{}{:039}", collection_prefix(collection_id), token_id)
+}
+
+fn collection_tokens(collection_id: &Principal) -> Vec<InventoryToken> {
+    let prefix = collection_prefix(collection_id);
+    LICENSE_INVENTORY.with(|inventory| {
+        inventory
+            .borrow()
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, token)| token)
+            .collect()
+    })
+}
+
+/// Account the license tokens are sold from: `<collection>_sale_account`, or the collection canister itself
+pub fn sale_account(collection_id: &Principal) -> Result<Principal, String> {
+    Ok(get_optional_config_principal(&format!("{}_sale_account", collection_id.to_text()))?
+        .unwrap_or(*collection_id))
+}
+
+/// Brings the inventory in line with the tokens the sale account holds. New tokens become
+/// available and available tokens that left the account are dropped; reserved and sold
+/// tokens are left alone.
+pub async fn sync_inventory(collection_id: Principal) -> Result<InventorySummary, String> {
+    let account = sale_account(&collection_id)?;
+    let held = UserNFTHolding::tokens_of(collection_id, account).await?;
+    let now = ic_cdk::api::time();
+
+    let existing = collection_tokens(&collection_id);
+    LICENSE_INVENTORY.with(|inventory| {
+        let mut inventory = inventory.borrow_mut();
+        for token in &existing {
+            if token.status == InventoryStatus::Available && !held.contains(&token.token_id) {
+                inventory.remove(&inventory_key(&collection_id, token.token_id));
+            }
+        }
+        for token_id in &held {
+            let key = inventory_key(&collection_id, *token_id);
+            match inventory.get(&key) {
+                Some(mut token) => {
+                    token.synced_at = now;
+                    inventory.insert(key, token);
+                }
+                None => {
+                    inventory.insert(key, InventoryToken {
+                        collection_id,
+                        token_id: *token_id,
+                        status: InventoryStatus::Available,
+                        synced_at: now,
+                    });
+                }
+            }
+        }
+    });
+    ic_cdk::println!("Synced license inventory for {}: {} tokens held by sale account", collection_id, held.len());
+    get_summary(collection_id)
+}
+
+pub fn get_summary(collection_id: Principal) -> Result<InventorySummary, String> {
+    let mut summary = InventorySummary {
+        collection_id,
+        sale_account: sale_account(&collection_id)?,
+        available: 0,
+        reserved: 0,
+        sold: 0,
+    };
+    for token in collection_tokens(&collection_id) {
+        match token.status {
+            InventoryStatus::Available => summary.available += 1,
+            InventoryStatus::Reserved { .. } => summary.reserved += 1,
+            InventoryStatus::Sold { .. } => summary.sold += 1,
+        }
+    }
+    Ok(summary)
+}
+
+pub fn available_count(collection_id: &Principal) -> u64 {
+    collection_tokens(collection_id)
+        .iter()
+        .filter(|t| t.status == InventoryStatus::Available)
+        .count() as u64
+}
+
+/// Reserves the lowest available token for a purchase. Runs without awaiting, so two
+/// concurrent purchases can never get the same token.
+pub fn reserve_token(collection_id: &Principal, purchase_id: u64) -> Result<u128, String> {
+    let prefix = collection_prefix(collection_id);
+    LICENSE_INVENTORY.with(|inventory| -> Result<u128, String> {
+        let mut inventory = inventory.borrow_mut();
+        let (key, mut token) = inventory
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .find(|(_, token)| token.status == InventoryStatus::Available)
+            .ok_or("No available tokens left")?;
+        token.status = InventoryStatus::Reserved { purchase_id, at: ic_cdk::api::time() };
+        let token_id = token.token_id;
+        inventory.insert(key, token);
+        Ok(token_id)
+    })
+}
+
+fn set_status(collection_id: &Principal, token_id: u128, purchase_id: u64, status: InventoryStatus) {
+    let key = inventory_key(collection_id, token_id);
+    LICENSE_INVENTORY.with(|inventory| {
+        let mut inventory = inventory.borrow_mut();
+        if let Some(mut token) = inventory.get(&key) {
+            // Only the purchase holding the reservation may settle it
+            if matches!(token.status, InventoryStatus::Reserved { purchase_id: id, .. } if id == purchase_id) {
+                token.status = status;
+                inventory.insert(key, token);
+            }
+        }
+    });
+}
+
+pub fn mark_sold(collection_id: &Principal, token_id: u128, purchase_id: u64) {
+    let at = ic_cdk::api::time();
+    set_status(collection_id, token_id, purchase_id, InventoryStatus::Sold { purchase_id, at });
+}
+
+/// Returns a reserved token to the available pool after a failed purchase
+pub fn release_token(collection_id: &Principal, token_id: u128, purchase_id: u64) {
+    set_status(collection_id, token_id, purchase_id, InventoryStatus::Available);
+}
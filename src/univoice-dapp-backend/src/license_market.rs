@@ -0,0 +1,405 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use serde::Serialize;
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
+use std::cell::RefCell;
+
+use crate::config_types::{get_config_nat_or, get_optional_config_principal};
+use crate::constants::DEFAULT_LICENSE_ROYALTY_BPS;
+use crate::license_catalog;
+use crate::license_purchases::{
+    LicensePrice, PaymentError, PurchaseKind, PurchaseRecord, PurchaseStatus, open_purchase, update_purchase, collect_payment,
+    ledger_fee, pay_out, check_idle, get_purchase, leave_pending, reconcilable, refund_and_record, settle_payment,
+};
+use crate::license_records;
+use crate::license_types::{self, Account__3, Delivery};
+use crate::nft_collection_cache;
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum ListingStatus {
+    Active,
+    // A buyer's payment is in flight; nobody else can buy meanwhile
+    Settling { buyer: Principal, purchase_id: u64 },
+    Sold { buyer: Principal, purchase_id: u64 },
+    Cancelled,
+}
+
+/// A license token a holder offers for resale
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct Listing {
+    pub id: u64,
+    pub seller: Principal,
+    pub collection_id: Principal,
+    pub token_id: u128,
+    pub price: u64,
+    pub ledger: Principal,
+    pub status: ListingStatus,
+    pub royalty: Option<u64>,
+    pub royalty_block: Option<u64>,
+    pub seller_block: Option<u64>,
+    // Set when the buyer got the token but a payout could not be sent; needs manual review
+    pub settlement_error: Option<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl Storable for Listing {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize Listing");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize Listing")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 1024,
+        is_fixed_size: false,
+    };
+}
+
+#[derive(CandidType, Deserialize)]
+struct IsApprovedArg {
+    spender: Account__3,
+    from_subaccount: Option<Vec<u8>>,
+    token_id: Nat,
+}
+
+thread_local! {
+    static LISTINGS: RefCell<StableBTreeMap<u64, Listing, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28)))
+        )
+    );
+}
+
+/// Royalty in basis points from `license_royalty_bps` and the `license_treasury` it is paid to
+fn royalty_terms() -> Result<(u64, Option<Principal>), String> {
+    let bps = get_config_nat_or("license_royalty_bps", DEFAULT_LICENSE_ROYALTY_BPS)?;
+    if bps > 10_000 {
+        return Err(format!("license_royalty_bps {} exceeds 10000", bps));
+    }
+    let treasury = get_optional_config_principal("license_treasury")?;
+    if bps > 0 && treasury.is_none() {
+        return Err("A resale royalty is configured but license_treasury is not set".to_string());
+    }
+    Ok((bps, treasury))
+}
+
+pub fn get_listing(id: u64) -> Option<Listing> {
+    LISTINGS.with(|listings| listings.borrow().get(&id))
+}
+
+fn update_listing(id: u64, update: impl FnOnce(&mut Listing)) -> Option<Listing> {
+    LISTINGS.with(|listings| {
+        let mut listings = listings.borrow_mut();
+        let mut listing = listings.get(&id)?;
+        update(&mut listing);
+        listing.updated_at = ic_cdk::api::time();
+        listings.insert(id, listing.clone());
+        Some(listing)
+    })
+}
+
+fn open_listing_for(collection_id: &Principal, token_id: u128) -> Option<Listing> {
+    LISTINGS.with(|listings| {
+        listings
+            .borrow()
+            .iter()
+            .map(|(_, l)| l)
+            .find(|l| {
+                l.collection_id == *collection_id
+                    && l.token_id == token_id
+                    && matches!(l.status, ListingStatus::Active | ListingStatus::Settling { .. })
+            })
+    })
+}
+
+// Whether the token's owner approved this canister to move it through ICRC-37
+async fn is_approved(collection_id: Principal, token_id: u128) -> Result<bool, String> {
+    let arg = IsApprovedArg {
+        spender: Account__3 { owner: ic_cdk::id(), subaccount: None },
+        from_subaccount: None,
+        token_id: Nat::from(token_id),
+    };
+    let (approved,) = ic_cdk::call::<(Vec<IsApprovedArg>,), (Vec<bool>,)>(collection_id, "icrc37_is_approved", (vec![arg],))
+        .await
+        .map_err(|e| format!("Failed to call icrc37_is_approved: {:?}", e))?;
+    Ok(approved.first().copied().unwrap_or(false))
+}
+
+/// Lists a held license token for resale. The seller must have approved this canister for
+/// the token with `icrc37_approve_tokens`; the price is paid in the catalog's ledger.
+pub async fn create_listing(seller: Principal, collection_id: Principal, token_id: u128, price: u64) -> Result<Listing, String> {
+    if price == 0 {
+        return Err("Listing price must be positive".to_string());
+    }
+    royalty_terms()?;
+    let entry = license_catalog::get_entry(&collection_id)
+        .ok_or(format!("Collection {} is not in the license catalog", collection_id))?;
+    let ledger = entry.payment_ledger
+        .ok_or(format!("{} has no payment ledger for resales", entry.display_name))?;
+    if open_listing_for(&collection_id, token_id).is_some() {
+        return Err(format!("Token {} is already listed", token_id));
+    }
+
+    let collection = nft_collection_cache::get_or_refresh(collection_id).await?;
+    if !collection.allowed_transfers {
+        return Err(format!("{} licenses cannot be resold", entry.display_name));
+    }
+    if license_types::token_owner(collection_id, token_id).await? != Some(seller) {
+        return Err(format!("Token {} is not held by {}", token_id, seller));
+    }
+    if !is_approved(collection_id, token_id).await? {
+        return Err(format!("Approve the backend for token {} with icrc37_approve_tokens first", token_id));
+    }
+
+    // Another listing may have been created while awaiting
+    if open_listing_for(&collection_id, token_id).is_some() {
+        return Err(format!("Token {} is already listed", token_id));
+    }
+    let now = ic_cdk::api::time();
+    LISTINGS.with(|listings| {
+        let mut listings = listings.borrow_mut();
+        let listing = Listing {
+            id: listings.last_key_value().map(|(id, _)| id + 1).unwrap_or(0),
+            seller,
+            collection_id,
+            token_id,
+            price,
+            ledger,
+            status: ListingStatus::Active,
+            royalty: None,
+            royalty_block: None,
+            seller_block: None,
+            settlement_error: None,
+            created_at: now,
+            updated_at: now,
+        };
+        listings.insert(listing.id, listing.clone());
+        Ok(listing)
+    })
+}
+
+/// Withdraws an active listing; only its seller or a controller may do so
+pub fn cancel_listing(caller: Principal, id: u64, is_admin: bool) -> Result<Listing, String> {
+    let listing = get_listing(id).ok_or(format!("Listing {} not found", id))?;
+    if listing.seller != caller && !is_admin {
+        return Err("Only the seller can cancel a listing".to_string());
+    }
+    if listing.status != ListingStatus::Active {
+        return Err(format!("Listing {} is not active", id));
+    }
+    update_listing(id, |l| l.status = ListingStatus::Cancelled).ok_or(format!("Listing {} not found", id))
+}
+
+/// Royalty and seller share of a resale price, the royalty rounded down. A royalty that does
+/// not cover the ledger `fee` of its own payout cannot be sent, so it goes to the seller.
+pub fn royalty_split(price: u64, royalty_bps: u64, fee: u64) -> (u64, u64) {
+    let royalty = (price as u128 * royalty_bps.min(10_000) as u128 / 10_000) as u64;
+    let royalty = if royalty > fee { royalty } else { 0 };
+    (royalty, price - royalty)
+}
+
+/// Buys a listing: the price is pulled from the buyer's ICRC-2 approval, the token moves
+/// from the seller with icrc37_transfer_from, then the royalty goes to the treasury and the
+/// rest to the seller. If the token was not delivered the buyer is refunded and the listing
+/// is cancelled. When a payment or transfer reply is lost the listing stays Settling until
+/// `reconcile_listing` settles it.
+pub async fn buy_listing(buyer: Principal, id: u64) -> Result<Listing, String> {
+    let (royalty_bps, treasury) = royalty_terms()?;
+    let listing = get_listing(id).ok_or(format!("Listing {} not found", id))?;
+    if listing.status != ListingStatus::Active {
+        return Err(format!("Listing {} is not available", id));
+    }
+    if listing.seller == buyer {
+        return Err("Sellers cannot buy their own listing".to_string());
+    }
+
+    // Lock the listing before the first await
+    let price = LicensePrice { price: listing.price, ledger: Some(listing.ledger) };
+    let purchase = open_purchase(buyer, listing.collection_id, &price, PurchaseKind::Resale { listing_id: id });
+    update_purchase(purchase.id, |p| p.token_id = Some(listing.token_id));
+    update_listing(id, |l| l.status = ListingStatus::Settling { buyer, purchase_id: purchase.id });
+
+    match collect_payment(&purchase).await {
+        Ok(block) => {
+            update_purchase(purchase.id, |p| p.payment_block = Some(block));
+        }
+        Err(PaymentError::Rejected(e)) => {
+            update_purchase(purchase.id, |p| p.status = PurchaseStatus::Failed(e.clone()));
+            update_listing(id, |l| l.status = ListingStatus::Active);
+            return Err(e);
+        }
+        Err(PaymentError::Unknown(e)) => return Err(leave_pending(purchase.id, e)),
+    }
+
+    deliver_resale(&listing, &purchase, royalty_bps, treasury).await
+}
+
+// Moves a paid listing's token to the buyer and pays the seller and treasury. A failed
+// transfer call is only refunded once icrc7_owner_of shows the seller still holds the token.
+async fn deliver_resale(
+    listing: &Listing,
+    purchase: &PurchaseRecord,
+    royalty_bps: u64,
+    treasury: Option<Principal>,
+) -> Result<Listing, String> {
+    let (id, buyer) = (listing.id, purchase.buyer);
+    if let Err(e) = license_types::transfer_nft_from(listing.collection_id, listing.seller, buyer, listing.token_id).await {
+        match license_types::check_delivery(listing.collection_id, listing.token_id, listing.seller, buyer).await {
+            Delivery::Delivered => {}
+            Delivery::NotDelivered => {
+                // The seller moved the token or revoked the approval, so the listing cannot be filled
+                update_listing(id, |l| l.status = ListingStatus::Cancelled);
+                ic_cdk::println!("Resale transfer for listing {} failed, refunding: {}", id, e);
+                return match refund_and_record(purchase).await {
+                    Ok(_) => Err(format!("Token transfer failed, payment refunded: {}", e)),
+                    Err(_) => Err(format!(
+                        "Token transfer failed and the refund failed, purchase {} needs manual review: {}",
+                        purchase.id, e
+                    )),
+                };
+            }
+            Delivery::Unknown(check) => return Err(leave_pending(purchase.id, format!("{}; {}", e, check))),
+        }
+    }
+    update_purchase(purchase.id, |p| p.status = PurchaseStatus::Completed);
+    license_records::transfer_license(&listing.collection_id.to_text(), listing.token_id, &buyer.to_text());
+
+    let mut errors = Vec::new();
+    let (mut royalty_block, mut seller_block) = (None, None);
+    let royalty = match ledger_fee(listing.ledger).await {
+        Ok(fee) => {
+            let (royalty, seller_share) = royalty_split(listing.price, royalty_bps, fee);
+            if let Some(treasury) = treasury.filter(|_| royalty > 0) {
+                match pay_out(listing.ledger, treasury, royalty, fee, purchase.id).await {
+                    Ok(block) => royalty_block = Some(block),
+                    Err(e) => errors.push(format!("royalty: {}", e)),
+                }
+            }
+            match pay_out(listing.ledger, listing.seller, seller_share, fee, purchase.id).await {
+                Ok(block) => seller_block = Some(block),
+                Err(e) => errors.push(format!("seller payout: {}", e)),
+            }
+            royalty
+        }
+        Err(e) => {
+            errors.push(format!("payouts: {}", e));
+            0
+        }
+    };
+    if !errors.is_empty() {
+        ic_cdk::println!("Settlement of listing {} incomplete: {}", id, errors.join("; "));
+    }
+
+    update_listing(id, |l| {
+        l.status = ListingStatus::Sold { buyer, purchase_id: purchase.id };
+        l.royalty = Some(royalty);
+        l.royalty_block = royalty_block;
+        l.seller_block = seller_block;
+        l.settlement_error = if errors.is_empty() { None } else { Some(errors.join("; ")) };
+    })
+    .ok_or(format!("Listing {} not found", id))
+}
+
+/// Settles a listing left in Settling by a lost reply or a trap, going by its purchase:
+/// a pending purchase is paid through ledger deduplication and delivered or refunded by
+/// who holds the token; a failed one reopens the listing and a refunded one cancels it. A
+/// purchase that completed before the payouts were recorded leaves the listing Sold with a
+/// settlement error, since the payouts cannot be re-sent safely.
+pub async fn reconcile_listing(id: u64) -> Result<Listing, String> {
+    let listing = get_listing(id).ok_or(format!("Listing {} not found", id))?;
+    let ListingStatus::Settling { buyer, purchase_id } = listing.status.clone() else {
+        return Err(format!("Listing {} is not settling", id));
+    };
+    let purchase = get_purchase(purchase_id).ok_or(format!("Purchase {} not found", purchase_id))?;
+    match purchase.status {
+        PurchaseStatus::Pending => {
+            reconcilable(&purchase)?;
+            let (royalty_bps, treasury) = royalty_terms()?;
+            if !settle_payment(&purchase).await? {
+                update_listing(id, |l| l.status = ListingStatus::Active);
+            } else {
+                let purchase = get_purchase(purchase_id).ok_or(format!("Purchase {} not found", purchase_id))?;
+                deliver_resale(&listing, &purchase, royalty_bps, treasury).await?;
+            }
+        }
+        status => {
+            check_idle(&format!("Listing {}", id), listing.updated_at)?;
+            update_listing(id, |l| match status {
+                PurchaseStatus::Failed(_) => l.status = ListingStatus::Active,
+                PurchaseStatus::Refunded | PurchaseStatus::RefundFailed(_) => l.status = ListingStatus::Cancelled,
+                _ => {
+                    l.status = ListingStatus::Sold { buyer, purchase_id };
+                    l.settlement_error = Some("Settlement interrupted, payouts need manual review".to_string());
+                }
+            });
+        }
+    }
+    get_listing(id).ok_or(format!("Listing {} not found", id))
+}
+
+/// Active listings, newest first, optionally for one collection
+pub fn list_active(collection_id: Option<Principal>, skip: usize, take: usize) -> Vec<Listing> {
+    LISTINGS.with(|listings| {
+        listings
+            .borrow()
+            .iter()
+            .rev()
+            .map(|(_, l)| l)
+            .filter(|l| l.status == ListingStatus::Active)
+            .filter(|l| collection_id.map_or(true, |c| l.collection_id == c))
+            .skip(skip)
+            .take(take)
+            .collect()
+    })
+}
+
+/// Every listing of a seller, newest first
+pub fn list_by_seller(seller: Principal) -> Vec<Listing> {
+    LISTINGS.with(|listings| {
+        listings.borrow().iter().rev().map(|(_, l)| l).filter(|l| l.seller == seller).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn royalty_split_rounds_royalty_down() {
+        assert_eq!(royalty_split(10_000, 500, 0), (500, 9_500));
+        assert_eq!(royalty_split(199, 500, 0), (9, 190));
+        assert_eq!(royalty_split(1, 9_999, 0), (0, 1));
+    }
+
+    #[test]
+    fn royalty_split_edges() {
+        assert_eq!(royalty_split(1_000, 0, 0), (0, 1_000));
+        assert_eq!(royalty_split(1_000, 10_000, 0), (1_000, 0));
+        assert_eq!(royalty_split(1_000, 20_000, 0), (1_000, 0));
+        assert_eq!(royalty_split(0, 500, 0), (0, 0));
+    }
+
+    #[test]
+    fn royalty_below_the_fee_goes_to_the_seller() {
+        assert_eq!(royalty_split(1_000, 500, 10), (50, 950));
+        assert_eq!(royalty_split(1_000, 500, 49), (50, 950));
+        assert_eq!(royalty_split(1_000, 500, 50), (0, 1_000));
+        assert_eq!(royalty_split(100_000, 500, 10_000), (0, 100_000));
+    }
+
+    #[test]
+    fn royalty_split_does_not_overflow() {
+        let (royalty, seller) = royalty_split(u64::MAX, 10_000, 0);
+        assert_eq!((royalty, seller), (u64::MAX, 0));
+        let (royalty, seller) = royalty_split(u64::MAX, 500, 0);
+        assert_eq!(royalty + seller, u64::MAX);
+        assert_eq!(royalty, u64::MAX / 20);
+    }
+}
@@ -0,0 +1,326 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use serde::Serialize;
+use icrc_ledger_types::icrc1::account::Account;
+use icrc_ledger_types::icrc1::transfer::{BlockIndex, Memo, NumTokens, TransferArg, TransferError};
+use icrc_ledger_types::icrc2::transfer_from::{TransferFromArgs, TransferFromError};
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
+use std::cell::RefCell;
+
+use crate::constants::PURCHASE_RECONCILE_AFTER_SECS;
+use crate::license_catalog::LicenseCatalogEntry;
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum PurchaseStatus {
+    Pending,
+    Completed,
+    Failed(String),
+    Refunded,
+    RefundFailed(String),
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum PurchaseKind {
+    License,
+    Renewal { license_id: u64 },
+    Resale { listing_id: u64 },
+}
+
+/// One license sale, linking the payment block to the NFT token it paid for
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct PurchaseRecord {
+    pub id: u64,
+    pub buyer: Principal,
+    pub collection_id: Principal,
+    pub token_id: Option<u128>,
+    pub price: u64,
+    pub ledger: Option<Principal>,
+    pub payment_block: Option<u64>,
+    pub refund_block: Option<u64>,
+    pub status: PurchaseStatus,
+    pub created_at: u64,
+    pub updated_at: u64,
+    // Records written before renewals existed are license sales
+    pub kind: Option<PurchaseKind>,
+    // Creation time sent with the payment; re-sending it lets the ledger deduplicate a retry
+    pub payment_created_at: Option<u64>,
+    // Why a pending purchase could not be settled yet
+    pub last_error: Option<String>,
+}
+
+impl Storable for PurchaseRecord {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize PurchaseRecord");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize PurchaseRecord")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 1024,
+        is_fixed_size: false,
+    };
+}
+
+thread_local! {
+    static PURCHASES: RefCell<StableBTreeMap<u64, PurchaseRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20)))
+        )
+    );
+}
+
+/// Why a payment was not collected. `Unknown` means the ledger call itself failed, so the
+/// funds may have moved anyway and the purchase must be reconciled rather than failed.
+#[derive(Clone, Debug)]
+pub enum PaymentError {
+    Rejected(String),
+    Unknown(String),
+}
+
+/// Price and payment ledger of a license, taken from its catalog entry
+pub struct LicensePrice {
+    pub price: u64,
+    pub ledger: Option<Principal>,
+}
+
+impl LicensePrice {
+    fn new(price: u64, entry: &LicenseCatalogEntry) -> Result<Self, String> {
+        if price == 0 {
+            return Ok(Self { price, ledger: None });
+        }
+        let ledger = entry.payment_ledger
+            .ok_or(format!("Payment ledger not configured for {}", entry.display_name))?;
+        Ok(Self { price, ledger: Some(ledger) })
+    }
+
+    pub fn for_purchase(entry: &LicenseCatalogEntry) -> Result<Self, String> {
+        Self::new(entry.price, entry)
+    }
+
+    /// Renewal price, falling back to the purchase price
+    pub fn for_renewal(entry: &LicenseCatalogEntry) -> Result<Self, String> {
+        Self::new(entry.renewal_price.unwrap_or(entry.price), entry)
+    }
+}
+
+/// Records a new pending purchase. Called before any await so the attempt is never lost.
+pub fn open_purchase(buyer: Principal, collection_id: Principal, price: &LicensePrice, kind: PurchaseKind) -> PurchaseRecord {
+    let now = ic_cdk::api::time();
+    PURCHASES.with(|purchases| {
+        let mut purchases = purchases.borrow_mut();
+        let record = PurchaseRecord {
+            id: purchases.last_key_value().map(|(id, _)| id + 1).unwrap_or(0),
+            buyer,
+            collection_id,
+            token_id: None,
+            price: price.price,
+            ledger: price.ledger,
+            payment_block: None,
+            refund_block: None,
+            status: PurchaseStatus::Pending,
+            created_at: now,
+            updated_at: now,
+            kind: Some(kind),
+            payment_created_at: None,
+            last_error: None,
+        };
+        purchases.insert(record.id, record.clone());
+        record
+    })
+}
+
+pub fn update_purchase(id: u64, update: impl FnOnce(&mut PurchaseRecord)) -> Option<PurchaseRecord> {
+    PURCHASES.with(|purchases| {
+        let mut purchases = purchases.borrow_mut();
+        let mut record = purchases.get(&id)?;
+        update(&mut record);
+        record.updated_at = ic_cdk::api::time();
+        purchases.insert(id, record.clone());
+        Some(record)
+    })
+}
+
+fn block_to_u64(block: &BlockIndex) -> Result<u64, String> {
+    block.0.clone().try_into().map_err(|_| format!("Block index {} does not fit in u64", block))
+}
+
+/// Pulls the price from the buyer's ICRC-2 approval into this canister's account.
+/// Calling it again for the same purchase re-sends the same memo and creation time, so the
+/// ledger returns the block of an earlier attempt that went through instead of charging twice.
+pub async fn collect_payment(record: &PurchaseRecord) -> Result<u64, PaymentError> {
+    let ledger = record.ledger.ok_or(PaymentError::Rejected("No payment ledger for a paid purchase".to_string()))?;
+    let created_at = get_purchase(record.id)
+        .and_then(|p| p.payment_created_at)
+        .unwrap_or_else(ic_cdk::api::time);
+    update_purchase(record.id, |p| p.payment_created_at = Some(created_at));
+    let args = TransferFromArgs {
+        from: Account { owner: record.buyer, subaccount: None },
+        to: Account { owner: ic_cdk::id(), subaccount: None },
+        amount: NumTokens::from(record.price),
+        fee: None,
+        memo: Some(Memo::from(record.id)),
+        created_at_time: Some(created_at),
+        spender_subaccount: None,
+    };
+    let (result,) = ic_cdk::call::<(TransferFromArgs,), (Result<BlockIndex, TransferFromError>,)>(
+        ledger,
+        "icrc2_transfer_from",
+        (args,),
+    )
+    .await
+    .map_err(|e| PaymentError::Unknown(format!("Call to payment ledger failed: {:?}", e)))?;
+
+    match result {
+        Ok(block) | Err(TransferFromError::Duplicate { duplicate_of: block }) => {
+            block_to_u64(&block).map_err(PaymentError::Unknown)
+        }
+        Err(TransferFromError::InsufficientAllowance { allowance }) => Err(PaymentError::Rejected(format!(
+            "Insufficient allowance: approved {}, price {}",
+            allowance, record.price
+        ))),
+        Err(TransferFromError::InsufficientFunds { balance }) => Err(PaymentError::Rejected(format!(
+            "Insufficient funds: balance {}, price {}",
+            balance, record.price
+        ))),
+        // Past the ledger's deduplication window a retry can no longer tell whether it paid
+        Err(TransferFromError::TooOld) => {
+            Err(PaymentError::Unknown("Payment is too old for the ledger to deduplicate".to_string()))
+        }
+        Err(e) => Err(PaymentError::Rejected(format!("Payment failed: {:?}", e))),
+    }
+}
+
+/// Pays a pending purchase whose payment outcome was lost; Ok(false) means it is now Failed
+pub async fn settle_payment(purchase: &PurchaseRecord) -> Result<bool, String> {
+    if purchase.price == 0 || purchase.payment_block.is_some() {
+        return Ok(true);
+    }
+    if purchase.payment_created_at.is_none() {
+        update_purchase(purchase.id, |p| p.status = PurchaseStatus::Failed("Interrupted before payment".to_string()));
+        return Ok(false);
+    }
+    match collect_payment(purchase).await {
+        Ok(block) => {
+            update_purchase(purchase.id, |p| p.payment_block = Some(block));
+            Ok(true)
+        }
+        Err(PaymentError::Rejected(e)) => {
+            update_purchase(purchase.id, |p| p.status = PurchaseStatus::Failed(e));
+            Ok(false)
+        }
+        Err(PaymentError::Unknown(e)) => Err(leave_pending(purchase.id, e)),
+    }
+}
+
+/// Keeps a purchase pending after an outcome was lost and returns the error for the caller
+pub fn leave_pending(id: u64, err: String) -> String {
+    ic_cdk::println!("Purchase {} left pending: {}", id, err);
+    update_purchase(id, |p| p.last_error = Some(err.clone()));
+    format!("Outcome of purchase {} is unknown, it will be reconciled: {}", id, err)
+}
+
+/// Whether a record last updated at `updated_at` has been idle long enough that no call
+/// settling it can still be in flight
+pub fn check_idle(what: &str, updated_at: u64) -> Result<(), String> {
+    let idle = ic_cdk::api::time().saturating_sub(updated_at);
+    if idle < PURCHASE_RECONCILE_AFTER_SECS * 1_000_000_000 {
+        return Err(format!(
+            "{} may still be settling, retry {} seconds after its last update",
+            what, PURCHASE_RECONCILE_AFTER_SECS
+        ));
+    }
+    Ok(())
+}
+
+/// Whether a purchase is pending and idle, so reconciling it cannot race its own calls
+pub fn reconcilable(record: &PurchaseRecord) -> Result<(), String> {
+    if record.status != PurchaseStatus::Pending {
+        return Err(format!("Purchase {} is not pending", record.id));
+    }
+    check_idle(&format!("Purchase {}", record.id), record.updated_at)
+}
+
+/// Returns a collected payment to the buyer. The ledger fee of the refund is deducted.
+pub async fn refund_payment(record: &PurchaseRecord) -> Result<u64, String> {
+    let ledger = record.ledger.ok_or("No payment ledger for a paid purchase")?;
+    let fee = ledger_fee(ledger).await.map_err(|e| format!("Refund failed: {}", e))?;
+    pay_out(ledger, record.buyer, record.price, fee, record.id)
+        .await
+        .map_err(|e| format!("Refund failed: {}", e))
+}
+
+/// Transfer fee of a payment ledger
+pub async fn ledger_fee(ledger: Principal) -> Result<u64, String> {
+    let (fee,) = ic_cdk::call::<(), (Nat,)>(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|e| format!("Failed to get ledger fee: {:?}", e))?;
+    fee.0.clone().try_into().map_err(|_| format!("Ledger fee {} does not fit in u64", fee))
+}
+
+/// Sends `amount` from this canister's account to `to`, deducting the ledger `fee` from it
+pub async fn pay_out(ledger: Principal, to: Principal, amount: u64, fee: u64, memo: u64) -> Result<u64, String> {
+    if amount <= fee {
+        return Err(format!("Amount {} does not cover the transfer fee {}", amount, fee));
+    }
+
+    let args = TransferArg {
+        from_subaccount: None,
+        to: Account { owner: to, subaccount: None },
+        fee: Some(Nat::from(fee)),
+        created_at_time: Some(ic_cdk::api::time()),
+        memo: Some(Memo::from(memo)),
+        amount: Nat::from(amount - fee),
+    };
+    let (result,) = ic_cdk::call::<(TransferArg,), (Result<BlockIndex, TransferError>,)>(
+        ledger,
+        "icrc1_transfer",
+        (args,),
+    )
+    .await
+    .map_err(|e| format!("Call to payment ledger failed: {:?}", e))?;
+
+    result
+        .map_err(|e| format!("Transfer failed: {:?}", e))
+        .and_then(|block| block_to_u64(&block))
+}
+
+/// Refunds a paid purchase whose delivery failed and records the outcome on it
+pub async fn refund_and_record(record: &PurchaseRecord) -> Result<u64, String> {
+    match refund_payment(record).await {
+        Ok(block) => {
+            update_purchase(record.id, |p| {
+                p.refund_block = Some(block);
+                p.status = PurchaseStatus::Refunded;
+            });
+            Ok(block)
+        }
+        Err(e) => {
+            ic_cdk::println!("Refund for purchase {} failed: {}", record.id, e);
+            update_purchase(record.id, |p| p.status = PurchaseStatus::RefundFailed(e.clone()));
+            Err(e)
+        }
+    }
+}
+
+pub fn get_purchase(id: u64) -> Option<PurchaseRecord> {
+    PURCHASES.with(|purchases| purchases.borrow().get(&id))
+}
+
+/// Purchases, newest first, optionally for a single buyer
+pub fn list_purchases(buyer: Option<Principal>) -> Vec<PurchaseRecord> {
+    PURCHASES.with(|purchases| {
+        purchases
+            .borrow()
+            .iter()
+            .rev()
+            .filter(|(_, p)| buyer.map_or(true, |buyer| p.buyer == buyer))
+            .map(|(_, p)| p)
+            .collect()
+    })
+}
@@ -0,0 +1,243 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+use std::cell::RefCell;
+
+use crate::license_purchases::{self, PurchaseRecord};
+use crate::license_types::UserLicenseRecord;
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+#[derive(Clone, Copy, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum LicenseStatusFilter {
+    All,
+    Active,
+    Expired,
+}
+
+impl LicenseStatusFilter {
+    fn matches(&self, record: &UserLicenseRecord, now_secs: u64) -> bool {
+        match self {
+            LicenseStatusFilter::All => true,
+            LicenseStatusFilter::Active => !record.is_expired(now_secs),
+            LicenseStatusFilter::Expired => record.is_expired(now_secs),
+        }
+    }
+}
+
+/// Whether `principal` holds a currently valid license of a collection
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct LicenseCheck {
+    pub valid: bool,
+    pub license_id: Option<u64>,
+    pub token_id: Option<u128>,
+    pub expired_at: Option<u64>,
+}
+
+/// A sold license together with the payment that bought it, for exports
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct LicenseSale {
+    pub license: UserLicenseRecord,
+    pub purchase: Option<PurchaseRecord>,
+}
+
+thread_local! {
+    static LICENSE_RECORDS: RefCell<StableBTreeMap<u64, UserLicenseRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22)))
+        )
+    );
+
+    // "<owner>:<zero-padded record id>" -> record id
+    static LICENSES_BY_OWNER: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23)))
+        )
+    );
+
+    // "<collection>:<zero-padded record id>" -> record id
+    static LICENSES_BY_COLLECTION: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24)))
+        )
+    );
+}
+
+fn index_key(prefix: &str, id: u64) -> String {
+    format!("{}:{:020}", prefix, id)
+}
+
+fn now_secs() -> u64 {
+    ic_cdk::api::time() / 1_000_000_000
+}
+
+/// Persists a license record, assigning its id
+pub fn store_license(mut record: UserLicenseRecord) -> UserLicenseRecord {
+    let id = LICENSE_RECORDS.with(|records| {
+        let mut records = records.borrow_mut();
+        let id = records.last_key_value().map(|(id, _)| id + 1).unwrap_or(0);
+        record.id = Some(id);
+        records.insert(id, record.clone());
+        id
+    });
+    LICENSES_BY_OWNER.with(|index| {
+        index.borrow_mut().insert(index_key(&record.owner, id), id);
+    });
+    LICENSES_BY_COLLECTION.with(|index| {
+        index.borrow_mut().insert(index_key(&record.nft_collection_id, id), id);
+    });
+    record
+}
+
+pub fn get_license(id: u64) -> Option<UserLicenseRecord> {
+    LICENSE_RECORDS.with(|records| records.borrow().get(&id))
+}
+
+/// Replaces a stored record, e.g. when a renewal moves its expiry
+pub fn update_license(record: &UserLicenseRecord) -> Result<(), String> {
+    let id = record.id.ok_or("License record has no id")?;
+    LICENSE_RECORDS.with(|records| {
+        let mut records = records.borrow_mut();
+        if !records.contains_key(&id) {
+            return Err(format!("License {} not found", id));
+        }
+        records.insert(id, record.clone());
+        Ok(())
+    })
+}
+
+fn ids_with_prefix(index: &'static std::thread::LocalKey<RefCell<StableBTreeMap<String, u64, Memory>>>, prefix: &str) -> Vec<u64> {
+    let prefix = format!("{}:", prefix);
+    index.with(|index| {
+        index
+            .borrow()
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, id)| id)
+            .collect()
+    })
+}
+
+/// Licenses of an owner, newest first
+pub fn list_by_owner(owner: &str, filter: LicenseStatusFilter, collection_id: Option<&str>) -> Vec<UserLicenseRecord> {
+    let now = now_secs();
+    ids_with_prefix(&LICENSES_BY_OWNER, owner)
+        .into_iter()
+        .rev()
+        .filter_map(get_license)
+        .filter(|r| collection_id.map_or(true, |c| r.nft_collection_id == c))
+        .filter(|r| filter.matches(r, now))
+        .collect()
+}
+
+/// Licenses of a collection, newest first
+pub fn list_by_collection(collection_id: &str, filter: LicenseStatusFilter) -> Vec<UserLicenseRecord> {
+    let now = now_secs();
+    ids_with_prefix(&LICENSES_BY_COLLECTION, collection_id)
+        .into_iter()
+        .rev()
+        .filter_map(get_license)
+        .filter(|r| filter.matches(r, now))
+        .collect()
+}
+
+/// Every sale in id order, joined with its purchase record, optionally for one collection
+pub fn export_sales(collection_id: Option<&str>, skip: usize, take: usize) -> Vec<LicenseSale> {
+    let ids: Vec<u64> = match collection_id {
+        Some(collection_id) => ids_with_prefix(&LICENSES_BY_COLLECTION, collection_id),
+        None => LICENSE_RECORDS.with(|records| records.borrow().iter().map(|(id, _)| id).collect()),
+    };
+    ids.into_iter()
+        .skip(skip)
+        .take(take)
+        .filter_map(get_license)
+        .map(|license| LicenseSale {
+            purchase: license.purchase_id.and_then(license_purchases::get_purchase),
+            license,
+        })
+        .collect()
+}
+
+/// Marks every lapsed, not yet flagged license and returns the owners affected
+pub fn flag_expired_licenses() -> Vec<String> {
+    let now = ic_cdk::api::time();
+    let now_secs = now / 1_000_000_000;
+    LICENSE_RECORDS.with(|records| {
+        let mut records = records.borrow_mut();
+        let expired: Vec<UserLicenseRecord> = records
+            .iter()
+            .map(|(_, r)| r)
+            .filter(|r| r.expired_flagged_at.is_none() && r.is_expired(now_secs))
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|mut record| {
+                let id = record.id?;
+                record.expired_flagged_at = Some(now);
+                let owner = record.owner.clone();
+                records.insert(id, record);
+                Some(owner)
+            })
+            .collect()
+    })
+}
+
+// Current license of a token; `records` is newest first, so the first match wins
+fn current_license(records: &[UserLicenseRecord], token_id: u128) -> Option<&UserLicenseRecord> {
+    records.iter().find(|r| r.token_id == token_id)
+}
+
+// Tokens sold before license records existed have no record and count as licensed for good
+fn token_licensed(record: Option<&UserLicenseRecord>, now_secs: u64) -> bool {
+    record.map_or(true, |r| !r.is_expired(now_secs))
+}
+
+/// Best license among the tokens `owner` currently holds, as reported by the collection;
+/// licenses without expiry win. Records still naming an earlier holder, left behind by a
+/// direct icrc7 transfer, are moved to `owner`.
+pub fn check_held_tokens(owner: &str, collection_id: &str, held: &[u128]) -> LicenseCheck {
+    let now = now_secs();
+    let records = list_by_collection(collection_id, LicenseStatusFilter::All);
+    let best = held
+        .iter()
+        .map(|token_id| (*token_id, current_license(&records, *token_id)))
+        .filter(|(_, record)| token_licensed(*record, now))
+        .max_by_key(|(_, record)| record.and_then(|r| r.expired_at).unwrap_or(u64::MAX));
+    for token_id in held {
+        if current_license(&records, *token_id).map_or(false, |r| r.owner != owner) {
+            transfer_license(collection_id, *token_id, owner);
+        }
+    }
+    LicenseCheck {
+        valid: best.is_some(),
+        license_id: best.and_then(|(_, r)| r.and_then(|r| r.id)),
+        token_id: best.map(|(token_id, _)| token_id),
+        expired_at: best.and_then(|(_, r)| r.and_then(|r| r.expired_at)),
+    }
+}
+
+/// Whether any of the tokens still carries an unexpired license
+pub fn has_licensed_token(collection_id: &str, token_ids: &[u128]) -> bool {
+    let now = now_secs();
+    let records = list_by_collection(collection_id, LicenseStatusFilter::All);
+    token_ids.iter().any(|token_id| token_licensed(current_license(&records, *token_id), now))
+}
+
+/// Moves the current license of a token to its new holder after a resale. The expiry
+/// carries over; returns None when the token was never sold through the backend.
+pub fn transfer_license(collection_id: &str, token_id: u128, new_owner: &str) -> Option<UserLicenseRecord> {
+    let mut record = list_by_collection(collection_id, LicenseStatusFilter::All)
+        .into_iter()
+        .find(|r| r.token_id == token_id)?;
+    let id = record.id?;
+    LICENSES_BY_OWNER.with(|index| {
+        let mut index = index.borrow_mut();
+        index.remove(&index_key(&record.owner, id));
+        index.insert(index_key(new_owner, id), id);
+    });
+    record.owner = new_owner.to_string();
+    LICENSE_RECORDS.with(|records| records.borrow_mut().insert(id, record.clone()));
+    Some(record)
+}
@@ -8,6 +8,17 @@ use icrc_ledger_types::{
 };
 use icrc_ledger_types::icrc::generic_metadata_value::MetadataValue;
 use crate::buss_types::get_info_by_key;
+use crate::config_types::{get_config_principal, get_optional_config_nat};
+use crate::license_catalog::{self, LicenseCatalogEntry};
+use crate::license_inventory;
+use crate::license_market;
+use crate::license_records;
+use crate::nft_collection_cache;
+use ic_stable_structures::{Storable, storable::Bound};
+use crate::license_purchases::{
+    LicensePrice, PaymentError, PurchaseKind, PurchaseRecord, PurchaseStatus, open_purchase, update_purchase, collect_payment,
+    get_purchase, leave_pending, reconcilable, refund_and_record, settle_payment,
+};
 
 type TransferResult = Result<Nat, TransferError>;
 
@@ -41,8 +52,27 @@ pub struct NFTCollection {
     pub total_supply: u128,
     pub owner: Principal,
     pub allowed_transfers: bool,
-    pub expired_at: Option<u64>
+    pub expired_at: Option<u64>,
+    // When the metadata was fetched into the collection cache
+    pub cached_at: Option<u64>,
 }
+
+impl Storable for NFTCollection {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize NFTCollection");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize NFTCollection")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 8192,
+        is_fixed_size: false,
+    };
+}
+
 impl NFTCollection {
     pub async fn init_nft_collection(collection_id: &str) -> Result<Self, String> {
         let principal = Principal::from_text(collection_id)
@@ -87,7 +117,8 @@ impl NFTCollection {
             total_supply: total_supply.0.0.try_into().unwrap_or(0),
             owner: principal,
             allowed_transfers: true,
-            expired_at: None
+            expired_at: None,
+            cached_at: None,
         })
     }
 }
@@ -100,123 +131,87 @@ pub struct UserNFTHolding {
     pub token_ids: Vec<u128>,
     pub expired_at: Option<u64>,
 }
+// Page size for icrc7_tokens_of; collections may cap it lower through icrc7_max_take_value
+const TOKENS_OF_PAGE_SIZE: u64 = 100;
+
+pub fn nat_to_u128(n: &Nat) -> Result<u128, String> {
+    n.0.clone().try_into().map_err(|_| format!("Token id {} does not fit in u128", n))
+}
+
 impl UserNFTHolding {
-    pub async fn total_nft_supply(nft_collection_id: &String) -> Result<Nat, String> {
-        ic_cdk::call::<(), (Nat,)>(
-            Principal::from_text(nft_collection_id).expect("Could not decode the principal."),
-            "icrc7_total_supply",
-            (),
+    /// Number of tokens of a collection held by `owner`'s default account
+    pub async fn balance_of(collection: Principal, owner: Principal) -> Result<u128, String> {
+        let account = Account { owner, subaccount: None };
+        let (balances,) = ic_cdk::call::<(Vec<Account>,), (Vec<Nat>,)>(
+            collection,
+            "icrc7_balance_of",
+            (vec![account],),
         )
         .await
-        .map_err(|e| format!("failed to call ledger: {:?}", e))
-        .map(|r| r.0)
+        .map_err(|e| format!("Failed to call icrc7_balance_of: {:?}", e))?;
+        balances.first().map(nat_to_u128).unwrap_or(Ok(0))
     }
-}
-impl UserNFTHolding {
-    async fn init_nft_tokens(nft_collection_id:String) -> Vec<Vec<Nat>> {
-        let mut tokens_shard: Vec<Nat> = Vec::new();
-        let mut nft_tokens_param: Vec<Vec<Nat>> = Vec::new();
-        let max_tokenid: Nat = Self::total_nft_supply(&nft_collection_id).await.expect("Failed to get total NFT supply");
-        ic_cdk::println!("Total Nft Supply is {}", &max_tokenid);
-    
-        let mut i: u128 = 0 as u128;
-        let mut i_shard: u128 = 0 as u128;
-    
-        let glb_shard_size = 50;
+
+    /// Token ids held by `owner`'s default account, fetched page by page
+    pub async fn tokens_of(collection: Principal, owner: Principal) -> Result<Vec<u128>, String> {
+        let account = Account { owner, subaccount: None };
+        let mut token_ids = Vec::new();
+        let mut prev: Option<Nat> = None;
         loop {
-            tokens_shard.push(Nat::from(i.clone()));
-            i += 1;
-            i_shard += 1;
-    
-            if Nat::from(i_shard).eq(&Nat::from(glb_shard_size as u128)) {
-                nft_tokens_param.push(tokens_shard.clone());
-                tokens_shard.clear();
-                i_shard = 0;
-                ic_cdk::println!("Init Nft shard to Index of {}", i);
+            let (page,) = ic_cdk::call::<(Account, Option<Nat>, Option<Nat>), (Vec<Nat>,)>(
+                collection,
+                "icrc7_tokens_of",
+                (account.clone(), prev.clone(), Some(Nat::from(TOKENS_OF_PAGE_SIZE))),
+            )
+            .await
+            .map_err(|e| format!("Failed to call icrc7_tokens_of: {:?}", e))?;
+
+            let last = match page.last() {
+                Some(last) => last.clone(),
+                None => break,
+            };
+            for token_id in &page {
+                token_ids.push(nat_to_u128(token_id)?);
             }
-    
-            if Nat::from(i) == max_tokenid {
+            // A short page means the collection has no more tokens for this account
+            if (page.len() as u64) < TOKENS_OF_PAGE_SIZE {
                 break;
             }
+            prev = Some(last);
         }
-        if tokens_shard.len() > 0 {
-            nft_tokens_param.push(tokens_shard.clone());
-            tokens_shard.clear();
-        }
-        return nft_tokens_param;
+        Ok(token_ids)
     }
 }
-impl NFTCollection {
-    pub async fn get_min_available_token_id(&self, principalid: &str) -> Result<Option<u128>, String> {
-        for token_id in 0..self.total_supply {
-            let owner: (Vec<Option<Account__3>>,) = ic_cdk::call(
-                self.owner,
-                "icrc7_owner_of",
-                (vec![Nat::from(token_id)],)
-            )
-            .await
-            .map_err(|e| format!("Failed to get owner: {:?}", e))?;
-            
-            if let Some(account) = owner.0.get(0).and_then(|a| a.as_ref()) {
-                if account.owner.to_string() != principalid {
-                    return Ok(Some(token_id));
-                }
-            }
+impl UserNFTHolding {
+    /// Whether the holding still unlocks anything: the collection-wide expiry has not passed
+    /// (0 means none) and at least one token carries an unexpired license
+    pub fn is_active(&self, now_secs: u64) -> bool {
+        if self.expired_at.map_or(false, |expired_at| expired_at > 0 && expired_at <= now_secs) {
+            return false;
         }
-        Ok(None)
+        crate::license_records::has_licensed_token(&self.nft_colletion_id, &self.token_ids)
     }
-}
 
-impl UserNFTHolding {
     pub async fn construct_user_nft_holding(
         owner: Principal,
-        nft_canister_key: &str,
+        collection: Principal,
     ) -> Result<Self, String> {
-        // Get NFT canister id from CommonInfoCfg
-        let nft_canister = match get_info_by_key(&nft_canister_key.to_string()) {
-            Some(info) => info.content,
-            None => return Err("NFT canister configuration not found".to_string()),
-        };
+        let nft_canister = collection.to_text();
 
-        // Call icrc7_owner_of on the NFT canister
-        // Get all token IDs from init_nft_tokens
-        let token_shards = Self::init_nft_tokens(nft_canister.clone()).await;
-        let mut owned_token_ids: Vec<Nat> = Vec::new();
-        
-        for shard in token_shards {
-            let accounts = ic_cdk::call::<(Vec<Nat>,), (Vec<Option<Account>>,)>(
-            Principal::from_text(&nft_canister).expect("Could not decode the principal."),
-            "icrc7_owner_of",
-            (shard.clone(),),
-            )
-            .await
-            .map_err(|e| format!("failed to call ledger: {:?}", e))?
-            .0;
-
-            // Check ownership for each token in the shard
-            for (token_id, account) in shard.iter().zip(accounts.iter()) {
-            if let Some(acc) = account {
-                if acc.owner == owner {
-                owned_token_ids.push(token_id.clone());
-                }
-            }
-            }
-        }
-
-        let token_ids = vec![owned_token_ids];
+        // Skip the token listing entirely for accounts that hold nothing
+        let token_ids = if Self::balance_of(collection, owner).await? == 0 {
+            Vec::new()
+        } else {
+            Self::tokens_of(collection, owner).await?
+        };
 
-        let token_ids: Vec<u128> = token_ids.into_iter()
-            .flatten()
-            .map(|n| n.0.try_into().unwrap())
-            .collect();
+        let expired_at = get_optional_config_nat(&format!("{}_nft_expired_at", nft_canister))?;
 
         Ok(Self {
             owner,
             nft_colletion_id: nft_canister.clone(),
             token_ids,
-                        expired_at: get_info_by_key(&format!("{}_nft_expired_at", nft_canister)).map(|info| 
-                            info.content.parse::<u64>().unwrap_or(0)
-            ),
+            expired_at,
         })
     }
 }
@@ -229,68 +224,62 @@ pub struct UserLicenseRecord {
     pub token_id: u128,
     pub purchase_time: u64,
     pub expired_at: Option<u64>,
+    // Set once the record is persisted
+    pub id: Option<u64>,
+    pub purchase_id: Option<u64>,
+    // When the expiry timer noticed the license had lapsed; cleared by a renewal
+    pub expired_flagged_at: Option<u64>,
+}
+
+impl Storable for UserLicenseRecord {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize UserLicenseRecord");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize UserLicenseRecord")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 1024,
+        is_fixed_size: false,
+    };
 }
+
+impl UserLicenseRecord {
+    /// Whether the license has expired at `now_secs` (license expiry is kept in seconds)
+    pub fn is_expired(&self, now_secs: u64) -> bool {
+        self.expired_at.map_or(false, |expired_at| expired_at <= now_secs)
+    }
+}
+
 impl UserLicenseRecord {
-    pub async fn new(
+    /// License for a token that has been delivered to `owner`
+    pub fn new(
         owner: String,
         nft_collection_id: String,
         license_name: String,
         token_id: u128,
-    ) -> Result<Self, String> {
+        duration_secs: Option<u64>,
+    ) -> Self {
         let now = ic_cdk::api::time();
         let seconds_now = now / 1_000_000_000;
 
+        // Licenses without a catalog duration never expire
+        let expired_at = duration_secs.map(|duration| seconds_now + duration);
 
-        // Transfer NFT using icrc37_transfer_from
-        let transfer_args = Icrc37_TransferFromArg {
-            token_id: Nat::from(token_id),
-            from: Account__3 {
-            owner: Principal::from_text(&nft_collection_id)
-                .map_err(|e| format!("Invalid NFT collection principal: {}", e))?,
-            subaccount: None,
-            },
-            to: Account__3 {
-            owner: Principal::from_text(&owner)
-                .map_err(|e| format!("Invalid owner principal: {}", e))?,
-            subaccount: None,
-            },
-            spender_subaccount: None,
-            memo: None,
-            created_at_time: None,
-        };
-
-        // The candid shows icrc37_transfer_from accepts vec of TransferFromArg and returns vec of opt TransferFromResult
-        ic_cdk::call::<(Vec<Icrc37_TransferFromArg>,), (Vec<Option<TransferFromResult>>,)>(
-            Principal::from_text(&nft_collection_id)
-            .map_err(|e| format!("Invalid NFT collection principal: {}", e))?,
-            "icrc37_transfer_from", 
-            (vec![transfer_args],),
-        )
-        .await
-        .map_err(|e| format!("Failed to transfer NFT: {:?}", e))?
-        .0
-        .get(0)
-        .ok_or("No transfer result returned")?
-        .as_ref()
-        .ok_or("Transfer failed")?;
-
-
-        // Set expiration
-        let expired_duration_key = format!("{}_expired_duration", nft_collection_id);
-        let expired_duration = get_info_by_key(&expired_duration_key)
-            .map(|info| info.content.parse::<u64>().unwrap_or(0))
-            .ok_or(format!("License expiration duration not found for {}", nft_collection_id))?;
-
-        let expired_at = Some(seconds_now + expired_duration);
-
-        Ok(Self {
+        Self {
             owner,
             nft_collection_id,
             license_name,
             token_id,
             purchase_time: seconds_now,
-            expired_at
-        })
+            expired_at,
+            id: None,
+            purchase_id: None,
+            expired_flagged_at: None,
+        }
     }
 }
 
@@ -305,25 +294,40 @@ pub struct UserNFTsResponse {
     pub holdings: Vec<UserNFTHolding>,
 }
 
-//nft_key: key-NFTCollection id; value-NFTName
+// Catalog license id first, then the legacy `nft_<id>` config key
+fn resolve_license_collection(license_id: &str) -> Option<Principal> {
+    if let Some(entry) = license_catalog::find_by_license_id(license_id) {
+        return Some(entry.collection_id);
+    }
+    let nft_key = format!("nft_{}", license_id);
+    get_info_by_key(&nft_key)?;
+    match get_config_principal(&nft_key) {
+        Ok(collection) => Some(collection),
+        Err(e) => {
+            ic_cdk::println!("NFT canister configuration invalid for {}: {}", nft_key, e);
+            None
+        }
+    }
+}
+
+/// Holdings of `user` in the given licenses; an empty list means every catalog collection
 pub async fn get_all_user_nfts(user: Principal, license_ids: Vec<String>) -> Result<Vec<UserNFTHolding>, String> {
     let mut holdings = Vec::new();
-    
-    // Construct NFT collection keys from license IDs
-    let nft_keys: Vec<String> = license_ids.iter()
-        .map(|id| format!("nft_{}", id))
-        .collect();
-
-    for nft_key in nft_keys {
-        if let Some(info) = get_info_by_key(&nft_key) {
-            match UserNFTHolding::construct_user_nft_holding(user, nft_key.as_str()).await {
-                Ok(holding) => {
-                    if !holding.token_ids.is_empty() {
-                        holdings.push(holding); 
-                    }
-                },
-                Err(e) => ic_cdk::println!("Error getting holdings for {}: {}", nft_key, e),
-            }
+
+    let collections: Vec<Principal> = if license_ids.is_empty() {
+        license_catalog::list_entries(true).into_iter().map(|e| e.collection_id).collect()
+    } else {
+        license_ids.iter().filter_map(|id| resolve_license_collection(id)).collect()
+    };
+
+    for collection in collections {
+        match UserNFTHolding::construct_user_nft_holding(user, collection).await {
+            Ok(holding) => {
+                if !holding.token_ids.is_empty() {
+                    holdings.push(holding); 
+                }
+            },
+            Err(e) => ic_cdk::println!("Error getting holdings for {}: {}", collection, e),
         }
     }
     
@@ -333,59 +337,322 @@ pub async fn get_all_user_nfts(user: Principal, license_ids: Vec<String>) -> Res
     Ok(holdings)
 }
 
-/// Retrieves information about an NFT collection by its collection ID.
-///
-/// # Arguments
-///
-/// * `collection_id` - A string slice containing the Principal ID of the NFT collection canister
-///
-/// # Returns
-///
-/// * `Result<NFTCollection, String>` - Returns an NFTCollection struct if successful, or an error message if the operation fails
+/// Cached metadata of an NFT collection. Never calls the collection canister, so it can
+/// back a query; the cache is filled by a timer, `refresh_nft_collection` and purchases.
 ///
 /// # Errors
 ///
 /// This function will return an error if:
 /// * The collection ID is not a valid Principal
-/// * The canister calls to fetch collection information fail
-/// * The collection metadata cannot be retrieved
-///
-/// # Example
-///
-/// ```
-/// let collection = get_nft_collection("rrkah-fqaaa-aaaaa-aaaaq-cai").await?;
-/// println!("Collection name: {}", collection.name);
-/// ```
-pub async fn get_nft_collection(collection_id: &str) -> Result<NFTCollection, String> {
-    NFTCollection::init_nft_collection(collection_id).await
+/// * The collection has not been cached yet
+pub fn get_nft_collection(collection_id: &str) -> Result<NFTCollection, String> {
+    let principal = Principal::from_text(collection_id)
+        .map_err(|e| format!("Invalid collection ID: {}", e))?;
+    nft_collection_cache::get_cached(&principal)
+        .ok_or(format!("Metadata of collection {} is not cached yet", collection_id))
 }
 
+/// Sells `amount` licenses of a collection to `user_principal`, one token at a time.
+/// Each token is paid for through the buyer's ICRC-2 approval before the NFT is transferred;
+/// if the transfer did not happen the payment is refunded. Every attempt leaves a
+/// PurchaseRecord; one whose payment or transfer outcome was lost stays pending until
+/// `reconcile_purchase` settles it.
 pub async fn buy_nft_license(
     user_principal: &str,
     nft_collection_id: &str,
     amount: u64 
 ) -> Result<Vec<UserLicenseRecord>, String> {
-    // Initialize NFT collection
-    let collection = NFTCollection::init_nft_collection(nft_collection_id).await?;
+    let buyer = Principal::from_text(user_principal)
+        .map_err(|e| format!("Invalid buyer principal: {}", e))?;
+    let collection_principal = Principal::from_text(nft_collection_id)
+        .map_err(|e| format!("Invalid NFT collection principal: {}", e))?;
+    let entry = license_catalog::get_sellable(&collection_principal)?;
+    let price = LicensePrice::for_purchase(&entry)?;
+    let sale_account = license_inventory::sale_account(&collection_principal)?;
+
+    let collection = nft_collection_cache::get_or_refresh(collection_principal).await?;
+
+    // Pick up tokens minted to the sale account since the last sync
+    if license_inventory::available_count(&collection_principal) < amount {
+        license_inventory::sync_inventory(collection_principal).await?;
+    }
     
     let mut records = Vec::new();
     
     // Find and process each token
     for _ in 0..amount {
-        // Get the next available token ID
-        let token_id = collection.get_min_available_token_id(user_principal).await?
-            .ok_or("No available tokens left")?;
-            
-        // Create license record
-        let record = UserLicenseRecord::new(
-            user_principal.to_string(),
-            nft_collection_id.to_string(),
-            collection.name.clone(),
-            token_id
-        ).await?;
-        
-        records.push(record);
+        let purchase = open_purchase(buyer, collection_principal, &price, PurchaseKind::License);
+        match sell_one_license(&collection, &entry, sale_account, &purchase).await {
+            Ok(record) => records.push(record),
+            Err(e) if records.is_empty() => return Err(e),
+            Err(e) => {
+                return Err(format!("{} of {} licenses purchased, then: {}", records.len(), amount, e));
+            }
+        }
     }
     
     Ok(records)
-}
\ No newline at end of file
+}
+
+async fn sell_one_license(
+    collection: &NFTCollection,
+    entry: &LicenseCatalogEntry,
+    sale_account: Principal,
+    purchase: &PurchaseRecord,
+) -> Result<UserLicenseRecord, String> {
+    let fail = |e: String| {
+        update_purchase(purchase.id, |p| p.status = PurchaseStatus::Failed(e.clone()));
+        e
+    };
+
+    // Reserve the token before charging so a sold-out collection costs nothing
+    let token_id = license_inventory::reserve_token(&collection.owner, purchase.id).map_err(fail)?;
+    update_purchase(purchase.id, |p| p.token_id = Some(token_id));
+
+    if purchase.price > 0 {
+        match collect_payment(purchase).await {
+            Ok(block) => {
+                update_purchase(purchase.id, |p| p.payment_block = Some(block));
+            }
+            Err(PaymentError::Rejected(e)) => {
+                license_inventory::release_token(&collection.owner, token_id, purchase.id);
+                return Err(fail(e));
+            }
+            // The token stays reserved until reconciliation learns whether the buyer paid
+            Err(PaymentError::Unknown(e)) => return Err(leave_pending(purchase.id, e)),
+        }
+    }
+
+    deliver_license(entry, sale_account, purchase, token_id).await
+}
+
+/// Who holds a token after a transfer call failed, which tells whether the transfer happened
+pub enum Delivery {
+    Delivered,
+    NotDelivered,
+    Unknown(String),
+}
+
+pub async fn check_delivery(collection: Principal, token_id: u128, from: Principal, to: Principal) -> Delivery {
+    match token_owner(collection, token_id).await {
+        Ok(Some(owner)) if owner == to => Delivery::Delivered,
+        Ok(Some(owner)) if owner == from => Delivery::NotDelivered,
+        Ok(owner) => Delivery::Unknown(format!("Token {} is held by {:?}", token_id, owner)),
+        Err(e) => Delivery::Unknown(e),
+    }
+}
+
+// Moves a reserved, paid token to the buyer. A failed transfer call is only undone once
+// icrc7_owner_of shows the sale account still holds the token.
+async fn deliver_license(
+    entry: &LicenseCatalogEntry,
+    sale_account: Principal,
+    purchase: &PurchaseRecord,
+    token_id: u128,
+) -> Result<UserLicenseRecord, String> {
+    let collection = purchase.collection_id;
+    if let Err(e) = transfer_nft_from(collection, sale_account, purchase.buyer, token_id).await {
+        match check_delivery(collection, token_id, sale_account, purchase.buyer).await {
+            Delivery::Delivered => {}
+            Delivery::NotDelivered => return Err(undo_sale(purchase, token_id, e).await),
+            Delivery::Unknown(check) => return Err(leave_pending(purchase.id, format!("{}; {}", e, check))),
+        }
+    }
+
+    license_inventory::mark_sold(&collection, token_id, purchase.id);
+    update_purchase(purchase.id, |p| p.status = PurchaseStatus::Completed);
+    let mut record = UserLicenseRecord::new(
+        purchase.buyer.to_text(),
+        collection.to_text(),
+        entry.display_name.clone(),
+        token_id,
+        entry.duration_secs,
+    );
+    record.purchase_id = Some(purchase.id);
+    Ok(license_records::store_license(record))
+}
+
+// Returns an undelivered token to the inventory and refunds a paid purchase
+async fn undo_sale(purchase: &PurchaseRecord, token_id: u128, err: String) -> String {
+    license_inventory::release_token(&purchase.collection_id, token_id, purchase.id);
+    if purchase.price == 0 {
+        update_purchase(purchase.id, |p| p.status = PurchaseStatus::Failed(err.clone()));
+        return err;
+    }
+    ic_cdk::println!("NFT transfer for purchase {} failed, refunding: {}", purchase.id, err);
+    match refund_and_record(purchase).await {
+        Ok(_) => format!("License transfer failed, payment refunded: {}", err),
+        Err(_) => format!(
+            "License transfer failed and the refund failed, purchase {} needs manual review: {}",
+            purchase.id, err
+        ),
+    }
+}
+
+/// Settles a pending purchase whose payment or transfer outcome was lost. The payment is
+/// re-sent, which the ledger deduplicates, then the token is delivered or the sale undone
+/// depending on who holds it. Must run within the ledger's deduplication window (24 hours
+/// on ICRC ledgers by default).
+pub async fn reconcile_purchase(id: u64) -> Result<PurchaseRecord, String> {
+    let purchase = get_purchase(id).ok_or(format!("Purchase {} not found", id))?;
+    reconcilable(&purchase)?;
+    match purchase.kind.clone().unwrap_or(PurchaseKind::License) {
+        PurchaseKind::License => reconcile_sale(&purchase).await?,
+        PurchaseKind::Renewal { license_id } => reconcile_renewal(&purchase, license_id).await?,
+        PurchaseKind::Resale { listing_id } => {
+            license_market::reconcile_listing(listing_id).await?;
+        }
+    }
+    get_purchase(id).ok_or(format!("Purchase {} not found", id))
+}
+
+async fn reconcile_sale(purchase: &PurchaseRecord) -> Result<(), String> {
+    let collection = purchase.collection_id;
+    let Some(token_id) = purchase.token_id else {
+        update_purchase(purchase.id, |p| p.status = PurchaseStatus::Failed("Interrupted before a token was reserved".to_string()));
+        return Ok(());
+    };
+    if !settle_payment(purchase).await? {
+        license_inventory::release_token(&collection, token_id, purchase.id);
+        return Ok(());
+    }
+    let entry = license_catalog::get_entry(&collection)
+        .ok_or(format!("Collection {} is not in the license catalog", collection))?;
+    let sale_account = license_inventory::sale_account(&collection)?;
+    let purchase = get_purchase(purchase.id).ok_or(format!("Purchase {} not found", purchase.id))?;
+    deliver_license(&entry, sale_account, &purchase, token_id).await.map(|_| ())
+}
+
+async fn reconcile_renewal(purchase: &PurchaseRecord, license_id: u64) -> Result<(), String> {
+    if !settle_payment(purchase).await? {
+        return Ok(());
+    }
+    let entry = license_catalog::get_entry(&purchase.collection_id)
+        .ok_or(format!("Collection {} is not in the license catalog", purchase.collection_id))?;
+    let duration = entry.duration_secs.ok_or(format!("{} licenses do not expire", entry.display_name))?;
+    extend_license(license_id, duration, purchase.id).map(|_| ())
+}
+
+/// Whether `owner` holds a valid license of a collection. Ownership is read from the
+/// collection itself, so tokens moved by a direct icrc7 transfer count for their new holder,
+/// and the holding and license record rules are the same ones storage quota uses.
+pub async fn check_license(owner: Principal, collection_id: &str) -> Result<license_records::LicenseCheck, String> {
+    let collection = Principal::from_text(collection_id)
+        .map_err(|e| format!("Invalid NFT collection principal: {}", e))?;
+    let holding = UserNFTHolding::construct_user_nft_holding(owner, collection).await?;
+    let now_secs = ic_cdk::api::time() / 1_000_000_000;
+    if !holding.is_active(now_secs) {
+        return Ok(license_records::check_held_tokens(&owner.to_text(), collection_id, &[]));
+    }
+    Ok(license_records::check_held_tokens(&owner.to_text(), collection_id, &holding.token_ids))
+}
+
+/// Moves a token with icrc37_transfer_from, spending the approval `from` gave this canister
+pub async fn transfer_nft_from(collection: Principal, from: Principal, to: Principal, token_id: u128) -> Result<(), String> {
+    let transfer_args = Icrc37_TransferFromArg {
+        token_id: Nat::from(token_id),
+        from: Account__3 { owner: from, subaccount: None },
+        to: Account__3 { owner: to, subaccount: None },
+        spender_subaccount: None,
+        memo: None,
+        created_at_time: None,
+    };
+
+    // The candid shows icrc37_transfer_from accepts vec of TransferFromArg and returns vec of opt TransferFromResult
+    let (results,) = ic_cdk::call::<(Vec<Icrc37_TransferFromArg>,), (Vec<Option<TransferFromResult>>,)>(
+        collection,
+        "icrc37_transfer_from",
+        (vec![transfer_args],),
+    )
+    .await
+    .map_err(|e| format!("Failed to transfer NFT: {:?}", e))?;
+
+    match results.into_iter().next().flatten() {
+        Some(Ok(_)) => Ok(()),
+        Some(Err(e)) => Err(format!("NFT transfer rejected: {:?}", e)),
+        None => Err("No transfer result returned".to_string()),
+    }
+}
+
+/// Current owner of a single token, if any
+pub async fn token_owner(collection: Principal, token_id: u128) -> Result<Option<Principal>, String> {
+    let (owners,) = ic_cdk::call::<(Vec<Nat>,), (Vec<Option<Account>>,)>(
+        collection,
+        "icrc7_owner_of",
+        (vec![Nat::from(token_id)],),
+    )
+    .await
+    .map_err(|e| format!("Failed to call icrc7_owner_of: {:?}", e))?;
+    Ok(owners.first().cloned().flatten().map(|account| account.owner))
+}
+
+/// Extends a license by its catalog duration for the renewal price.
+/// Renewing before expiry extends from the current expiry, afterwards from now.
+pub async fn renew_license(owner: Principal, license_id: u64) -> Result<UserLicenseRecord, String> {
+    let record = license_records::get_license(license_id).ok_or(format!("License {} not found", license_id))?;
+    if record.owner != owner.to_text() {
+        return Err("Only the license owner can renew it".to_string());
+    }
+    let collection = Principal::from_text(&record.nft_collection_id)
+        .map_err(|e| format!("Invalid NFT collection principal: {}", e))?;
+    let entry = license_catalog::get_entry(&collection)
+        .ok_or(format!("Collection {} is not in the license catalog", collection))?;
+    let duration = entry.duration_secs.ok_or(format!("{} licenses do not expire", entry.display_name))?;
+    let price = LicensePrice::for_renewal(&entry)?;
+
+    // The license follows the NFT, so the renewer must still hold the token
+    if token_owner(collection, record.token_id).await? != Some(owner) {
+        return Err(format!("Token {} is no longer held by {}", record.token_id, owner));
+    }
+
+    let purchase = open_purchase(owner, collection, &price, PurchaseKind::Renewal { license_id });
+    update_purchase(purchase.id, |p| p.token_id = Some(record.token_id));
+    if purchase.price > 0 {
+        match collect_payment(&purchase).await {
+            Ok(block) => {
+                update_purchase(purchase.id, |p| p.payment_block = Some(block));
+            }
+            Err(PaymentError::Rejected(e)) => {
+                update_purchase(purchase.id, |p| p.status = PurchaseStatus::Failed(e.clone()));
+                return Err(e);
+            }
+            Err(PaymentError::Unknown(e)) => return Err(leave_pending(purchase.id, e)),
+        }
+    }
+    extend_license(license_id, duration, purchase.id)
+}
+
+// Adds a paid renewal's duration. Re-reads the license so concurrent renewals each add theirs.
+fn extend_license(license_id: u64, duration: u64, purchase_id: u64) -> Result<UserLicenseRecord, String> {
+    let mut record = license_records::get_license(license_id).ok_or(format!("License {} not found", license_id))?;
+    let now_secs = ic_cdk::api::time() / 1_000_000_000;
+    let base = record.expired_at.unwrap_or(now_secs).max(now_secs);
+    record.expired_at = Some(base + duration);
+    record.expired_flagged_at = None;
+    license_records::update_license(&record)?;
+    update_purchase(purchase_id, |p| p.status = PurchaseStatus::Completed);
+    ic_cdk::println!("Renewed license {} for {} until {}", license_id, record.owner, base + duration);
+    Ok(record)
+}
+
+/// Flags licenses that have lapsed and recomputes their owners' quota. Called from a timer.
+pub async fn enforce_license_expiry() -> u64 {
+    let owners = license_records::flag_expired_licenses();
+    let flagged = owners.len() as u64;
+    let mut seen = std::collections::HashSet::new();
+    for owner in owners {
+        if !seen.insert(owner.clone()) {
+            continue;
+        }
+        match Principal::from_text(&owner) {
+            Ok(owner) => {
+                crate::storage_quota::refresh_user_quota(owner, true).await;
+            }
+            Err(e) => ic_cdk::println!("Skipping quota downgrade for invalid owner {}: {}", owner, e),
+        }
+    }
+    if flagged > 0 {
+        ic_cdk::println!("Flagged {} expired licenses", flagged);
+    }
+    flagged
+}
@@ -0,0 +1,11 @@
+use ic_stable_structures::memory_manager::MemoryManager;
+use ic_stable_structures::DefaultMemoryImpl;
+use std::cell::RefCell;
+
+// The one memory manager of the canister. Every module takes its MemoryId from here: a manager
+// keeps the bucket allocation table in heap, so a second manager over the same stable memory
+// would hand out buckets this one already owns.
+thread_local! {
+    pub static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
@@ -0,0 +1,140 @@
+use candid::{CandidType, Deserialize, Int, Nat, Principal};
+use serde_bytes::ByteBuf;
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+use std::cell::RefCell;
+
+use crate::config_types::get_config_nat_or;
+use crate::license_catalog;
+use crate::constants::DEFAULT_COLLECTION_METADATA_TTL_SECS;
+use crate::license_types::{nat_to_u128, NFTCollection};
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// ICRC-3 value, as returned by `icrc7_collection_metadata`
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum Icrc7Value {
+    Nat(Nat),
+    Int(Int),
+    Text(String),
+    Blob(ByteBuf),
+    Array(Vec<Icrc7Value>),
+    Map(Vec<(String, Icrc7Value)>),
+}
+
+thread_local! {
+    // Keyed by collection principal text
+    static COLLECTION_CACHE: RefCell<StableBTreeMap<String, NFTCollection, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25)))
+        )
+    );
+}
+
+fn ttl_ns() -> u64 {
+    get_config_nat_or("collection_metadata_ttl_secs", DEFAULT_COLLECTION_METADATA_TTL_SECS)
+        .unwrap_or(DEFAULT_COLLECTION_METADATA_TTL_SECS)
+        * 1_000_000_000
+}
+
+fn is_fresh(collection: &NFTCollection, now: u64) -> bool {
+    collection.cached_at.map_or(false, |at| now.saturating_sub(at) < ttl_ns())
+}
+
+// One call for everything the collection publishes; the standard keys are "icrc7:<field>"
+async fn fetch_from_metadata(collection_id: Principal) -> Result<NFTCollection, String> {
+    let (metadata,) = ic_cdk::call::<(), (Vec<(String, Icrc7Value)>,)>(collection_id, "icrc7_collection_metadata", ())
+        .await
+        .map_err(|e| format!("Failed to get collection metadata: {:?}", e))?;
+    let value = |key: &str| metadata.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+    let text = |key: &str| match value(key) {
+        Some(Icrc7Value::Text(text)) => Some(text.clone()),
+        _ => None,
+    };
+    let nat = |key: &str| -> Result<Option<u128>, String> {
+        match value(key) {
+            Some(Icrc7Value::Nat(n)) => nat_to_u128(n).map(Some),
+            _ => Ok(None),
+        }
+    };
+
+    Ok(NFTCollection {
+        name: text("icrc7:name").ok_or("Collection metadata has no icrc7:name")?,
+        symbol: text("icrc7:symbol").ok_or("Collection metadata has no icrc7:symbol")?,
+        description: text("icrc7:description"),
+        logo: text("icrc7:logo"),
+        supply_cap: nat("icrc7:supply_cap")?,
+        total_supply: nat("icrc7:total_supply")?.ok_or("Collection metadata has no icrc7:total_supply")?,
+        owner: collection_id,
+        allowed_transfers: true,
+        expired_at: None,
+        cached_at: None,
+    })
+}
+
+/// Fetches a collection's metadata and stores it in the cache. Collections that do not
+/// implement `icrc7_collection_metadata` are read field by field instead.
+pub async fn refresh(collection_id: Principal) -> Result<NFTCollection, String> {
+    let mut collection = match fetch_from_metadata(collection_id).await {
+        Ok(collection) => collection,
+        Err(e) => {
+            ic_cdk::println!("Falling back to per-field calls for {}: {}", collection_id, e);
+            NFTCollection::init_nft_collection(&collection_id.to_text()).await?
+        }
+    };
+    collection.cached_at = Some(ic_cdk::api::time());
+    COLLECTION_CACHE.with(|cache| cache.borrow_mut().insert(collection_id.to_text(), collection.clone()));
+    Ok(collection)
+}
+
+/// Cached metadata, however old; never calls the collection, so it is safe in queries
+pub fn get_cached(collection_id: &Principal) -> Option<NFTCollection> {
+    COLLECTION_CACHE.with(|cache| cache.borrow().get(&collection_id.to_text()))
+}
+
+/// Cached metadata if within the TTL, otherwise refreshed. A failed refresh falls back
+/// to the stale entry when there is one.
+pub async fn get_or_refresh(collection_id: Principal) -> Result<NFTCollection, String> {
+    let cached = get_cached(&collection_id);
+    if let Some(collection) = &cached {
+        if is_fresh(collection, ic_cdk::api::time()) {
+            return Ok(collection.clone());
+        }
+    }
+    match (refresh(collection_id).await, cached) {
+        (Ok(collection), _) => Ok(collection),
+        (Err(e), Some(stale)) => {
+            ic_cdk::println!("Serving stale metadata for {}: {}", collection_id, e);
+            Ok(stale)
+        }
+        (Err(e), None) => Err(e),
+    }
+}
+
+/// Whether the canister tracks a collection's metadata: only collections in the license catalog
+pub fn is_known(collection_id: &Principal) -> bool {
+    license_catalog::get_entry(collection_id).is_some()
+}
+
+// Every collection in the license catalog. Cached entries of other collections are not
+// refreshed, so callers cannot grow the set of polled canisters.
+fn known_collections() -> Vec<Principal> {
+    license_catalog::list_entries(true).into_iter().map(|entry| entry.collection_id).collect()
+}
+
+/// Refreshes every known collection whose entry is missing or past the TTL. Called from a timer.
+pub async fn refresh_stale() -> u32 {
+    let mut refreshed = 0;
+    for collection_id in known_collections() {
+        let now = ic_cdk::api::time();
+        if get_cached(&collection_id).map_or(false, |c| is_fresh(&c, now)) {
+            continue;
+        }
+        match refresh(collection_id).await {
+            Ok(_) => refreshed += 1,
+            Err(e) => ic_cdk::println!("Failed to refresh metadata for {}: {}", collection_id, e),
+        }
+    }
+    refreshed
+}
@@ -0,0 +1,291 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
+use std::cell::RefCell;
+
+use crate::ic_oss_dapp;
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// A set of ic-oss policies attached to a subject on a bucket
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct PolicyGrant {
+    pub id: u64,
+    pub subject: Principal,
+    pub bucket_id: Principal,
+    pub cluster_id: Principal,
+    pub policies: String,
+    pub granted_by: Principal,
+    pub granted_at: u64,
+    // Nanosecond timestamp after which the grant is detached automatically
+    pub expires_at: Option<u64>,
+    pub revoked_at: Option<u64>,
+}
+
+impl PolicyGrant {
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none()
+    }
+}
+
+impl Storable for PolicyGrant {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize PolicyGrant");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize PolicyGrant")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 2048,
+        is_fixed_size: false,
+    };
+}
+
+thread_local! {
+    static POLICY_GRANTS: RefCell<StableBTreeMap<u64, PolicyGrant, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)))
+        )
+    );
+}
+
+// Wildcards are not accepted, so every grant names the resources and operations it covers
+const RESOURCES: [&str; 4] = ["Cluster", "Bucket", "Folder", "File"];
+const OPERATIONS: [&str; 4] = ["List", "Read", "Write", "Delete"];
+// Keeps a grant well inside PolicyGrant's stable bound
+const POLICIES_MAX_LEN: usize = 1024;
+
+/// Validates an ic-oss policy string.
+/// Policies are separated by whitespace and follow `Resource.Operation[.Scope][:Constraint]`,
+/// e.g. `Folder.Read:12 Folder.Write:12` or `Bucket.Read.Info`.
+pub fn validate_policies(policies: &str) -> Result<(), String> {
+    if policies.len() > POLICIES_MAX_LEN {
+        return Err(format!("Policies must be at most {} bytes", POLICIES_MAX_LEN));
+    }
+    let items: Vec<&str> = policies.split_whitespace().collect();
+    if items.is_empty() {
+        return Err("Policies cannot be empty".to_string());
+    }
+
+    for item in items {
+        let (permission, constraint) = match item.split_once(':') {
+            Some((permission, constraint)) => (permission, Some(constraint)),
+            None => (item, None),
+        };
+
+        let parts: Vec<&str> = permission.split('.').collect();
+        if parts.len() < 2 || parts.len() > 3 {
+            return Err(format!("Invalid policy '{}': expected Resource.Operation", item));
+        }
+        if !RESOURCES.contains(&parts[0]) {
+            return Err(format!("Invalid policy '{}': unknown resource {}", item, parts[0]));
+        }
+        if !OPERATIONS.contains(&parts[1]) {
+            return Err(format!("Invalid policy '{}': unknown operation {}", item, parts[1]));
+        }
+        if let Some(scope) = parts.get(2) {
+            if scope.is_empty() || !scope.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(format!("Invalid policy '{}': bad scope {}", item, scope));
+            }
+        }
+        if let Some(constraint) = constraint {
+            let valid = !constraint.is_empty()
+                && constraint
+                    .split(',')
+                    .all(|c| !c.is_empty() && c.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_'));
+            if !valid {
+                return Err(format!("Invalid policy '{}': bad constraint {}", item, constraint));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_principal(text: &str, what: &str) -> Result<Principal, String> {
+    Principal::from_text(text).map_err(|e| format!("Invalid {} principal: {}", what, e))
+}
+
+fn next_grant_id() -> u64 {
+    POLICY_GRANTS.with(|grants| {
+        grants.borrow().last_key_value().map(|(id, _)| id + 1).unwrap_or(0)
+    })
+}
+
+/// Attaches policies through the cluster and records the grant
+pub async fn attach_policies(
+    bucket_id: String,
+    cluster_id: String,
+    principal_id: String,
+    policies: String,
+    expires_at: Option<u64>,
+) -> Result<PolicyGrant, String> {
+    validate_policies(&policies)?;
+    let subject = parse_principal(&principal_id, "subject")?;
+    let bucket = parse_principal(&bucket_id, "bucket")?;
+    let cluster = parse_principal(&cluster_id, "cluster")?;
+    let now = ic_cdk::api::time();
+    if let Some(expires_at) = expires_at {
+        if expires_at <= now {
+            return Err("Grant expiration must be in the future".to_string());
+        }
+    }
+    let granted_by = ic_cdk::caller();
+
+    ic_oss_dapp::attach_policies(bucket_id, cluster_id, principal_id, policies.clone()).await?;
+
+    let grant = PolicyGrant {
+        id: next_grant_id(),
+        subject,
+        bucket_id: bucket,
+        cluster_id: cluster,
+        policies,
+        granted_by,
+        granted_at: now,
+        expires_at,
+        revoked_at: None,
+    };
+    POLICY_GRANTS.with(|grants| {
+        grants.borrow_mut().insert(grant.id, grant.clone());
+    });
+    Ok(grant)
+}
+
+/// Detaches policies through the cluster and marks matching active grants as revoked
+pub async fn detach_policies(
+    bucket_id: String,
+    cluster_id: String,
+    principal_id: String,
+    policies: String,
+) -> Result<(), String> {
+    // Not validated against the grammar, so grants recorded before wildcards were rejected
+    // can still be detached
+    if policies.trim().is_empty() || policies.len() > POLICIES_MAX_LEN {
+        return Err(format!("Policies must be 1 to {} bytes", POLICIES_MAX_LEN));
+    }
+    let subject = parse_principal(&principal_id, "subject")?;
+    let bucket = parse_principal(&bucket_id, "bucket")?;
+
+    ic_oss_dapp::detach_policies(bucket_id, cluster_id, principal_id, policies.clone()).await?;
+
+    let now = ic_cdk::api::time();
+    POLICY_GRANTS.with(|grants| {
+        let mut grants = grants.borrow_mut();
+        let revoked: Vec<PolicyGrant> = grants
+            .iter()
+            .filter(|(_, g)| g.is_active() && g.subject == subject && g.bucket_id == bucket && g.policies == policies)
+            .map(|(_, g)| g)
+            .collect();
+        for mut grant in revoked {
+            grant.revoked_at = Some(now);
+            grants.insert(grant.id, grant);
+        }
+    });
+    Ok(())
+}
+
+/// Detaches every active grant whose expiration has passed. Called from a timer.
+pub async fn detach_expired_grants() -> u64 {
+    let now = ic_cdk::api::time();
+    let expired: Vec<PolicyGrant> = POLICY_GRANTS.with(|grants| {
+        grants
+            .borrow()
+            .iter()
+            .filter(|(_, g)| g.is_active() && g.expires_at.map_or(false, |e| e <= now))
+            .map(|(_, g)| g)
+            .collect()
+    });
+
+    let mut detached = 0;
+    for grant in expired {
+        let result = ic_oss_dapp::detach_policies(
+            grant.bucket_id.to_text(),
+            grant.cluster_id.to_text(),
+            grant.subject.to_text(),
+            grant.policies.clone(),
+        )
+        .await;
+        match result {
+            Ok(_) => {
+                POLICY_GRANTS.with(|grants| {
+                    let mut grants = grants.borrow_mut();
+                    if let Some(mut stored) = grants.get(&grant.id) {
+                        stored.revoked_at = Some(ic_cdk::api::time());
+                        grants.insert(stored.id, stored);
+                    }
+                });
+                detached += 1;
+            }
+            // Left active so the next run retries it
+            Err(e) => ic_cdk::println!("Failed to detach expired grant {}: {}", grant.id, e),
+        }
+    }
+    if detached > 0 {
+        ic_cdk::println!("Detached {} expired policy grants", detached);
+    }
+    detached
+}
+
+pub fn list_grants_by_subject(subject: Principal, include_revoked: bool) -> Vec<PolicyGrant> {
+    POLICY_GRANTS.with(|grants| {
+        grants
+            .borrow()
+            .iter()
+            .filter(|(_, g)| g.subject == subject && (include_revoked || g.is_active()))
+            .map(|(_, g)| g)
+            .collect()
+    })
+}
+
+pub fn list_grants_by_bucket(bucket_id: Principal, include_revoked: bool) -> Vec<PolicyGrant> {
+    POLICY_GRANTS.with(|grants| {
+        grants
+            .borrow()
+            .iter()
+            .filter(|(_, g)| g.bucket_id == bucket_id && (include_revoked || g.is_active()))
+            .map(|(_, g)| g)
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_policies_accepts_scoped_policies() {
+        assert!(validate_policies("Folder.Read:12 Folder.Write:12").is_ok());
+        assert!(validate_policies("Bucket.Read.Info").is_ok());
+        assert!(validate_policies("File.Read:1,2,3").is_ok());
+        assert!(validate_policies("  File.List:a-b_c  ").is_ok());
+    }
+
+    #[test]
+    fn validate_policies_rejects_wildcards_and_malformed_items() {
+        assert!(validate_policies("").is_err());
+        assert!(validate_policies("   ").is_err());
+        assert!(validate_policies("*.*").is_err());
+        assert!(validate_policies("Folder.*:1").is_err());
+        assert!(validate_policies("Folder.Read:*").is_err());
+        assert!(validate_policies("Folder").is_err());
+        assert!(validate_policies("Folder.Read.Info.Extra").is_err());
+        assert!(validate_policies("Canister.Read").is_err());
+        assert!(validate_policies("Folder.Manage").is_err());
+        assert!(validate_policies("Folder.Read.").is_err());
+        assert!(validate_policies("Folder.Read:").is_err());
+        assert!(validate_policies("Folder.Read:1,,2").is_err());
+    }
+
+    #[test]
+    fn validate_policies_caps_length() {
+        let item = "File.Read:1 ";
+        let policies = item.repeat(POLICIES_MAX_LEN / item.len() + 1);
+        assert!(policies.len() > POLICIES_MAX_LEN);
+        assert!(validate_policies(&policies).is_err());
+        assert!(validate_policies(&item.repeat(POLICIES_MAX_LEN / item.len())).is_ok());
+    }
+}
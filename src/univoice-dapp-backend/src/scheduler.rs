@@ -0,0 +1,201 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
+use std::cell::RefCell;
+
+use crate::buss_types;
+use crate::config_types::{ConfigValueType, infer_value_type, validate_config_value, validate_value_type};
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const SCHEDULED_CHANGE_MAX_SIZE: u32 = 5120;
+// Room kept for the failure message of a change; longer messages are cut to fit
+const SCHEDULE_STATUS_MESSAGE_RESERVE: usize = 512;
+
+/// A config mutation to apply once its time has come
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub enum ScheduledAction {
+    Set { content: String, value_type: Option<ConfigValueType> },
+    Invalidate,
+    Revalidate,
+    Delete,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum ScheduleStatus {
+    Pending,
+    Executed,
+    Failed(String),
+    Cancelled,
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct ScheduledConfigChange {
+    pub id: u64,
+    pub key: String,
+    pub action: ScheduledAction,
+    // Nanosecond timestamp at or after which the change is applied
+    pub execute_at: u64,
+    pub created_by: Principal,
+    pub created_at: u64,
+    pub status: ScheduleStatus,
+    pub executed_at: Option<u64>,
+}
+
+impl Storable for ScheduledConfigChange {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize ScheduledConfigChange");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize ScheduledConfigChange")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: SCHEDULED_CHANGE_MAX_SIZE,
+        is_fixed_size: false,
+    };
+}
+
+thread_local! {
+    static SCHEDULED_CHANGES: RefCell<StableBTreeMap<u64, ScheduledConfigChange, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15)))
+        )
+    );
+}
+
+/// Schedules a config change. `Set` content is validated now so a bad value is rejected up front.
+pub fn schedule_change(key: String, action: ScheduledAction, execute_at: u64) -> Result<ScheduledConfigChange, String> {
+    if key.is_empty() {
+        return Err("Key cannot be empty".to_string());
+    }
+    let now = ic_cdk::api::time();
+    if execute_at <= now {
+        return Err("Scheduled time must be in the future".to_string());
+    }
+    if let ScheduledAction::Set { content, value_type } = &action {
+        let value_type = value_type.clone()
+            .or_else(|| buss_types::get_info_item_any(&key).and_then(|info| info.value_type))
+            .or_else(|| infer_value_type(&key));
+        if let Some(value_type) = &value_type {
+            validate_value_type(value_type)?;
+            validate_config_value(value_type, content)
+                .map_err(|e| format!("Invalid value for {}: {}", key, e))?;
+        }
+        // The entry is stored when the change runs, so it must fit then as well
+        buss_types::check_info_size(&buss_types::CommonInfoCfg::create_info(key.clone(), content.clone(), value_type))?;
+    }
+
+    SCHEDULED_CHANGES.with(|changes| {
+        let mut changes = changes.borrow_mut();
+        let change = ScheduledConfigChange {
+            id: changes.last_key_value().map(|(id, _)| id + 1).unwrap_or(0),
+            key,
+            action,
+            execute_at,
+            created_by: ic_cdk::caller(),
+            created_at: now,
+            status: ScheduleStatus::Pending,
+            executed_at: None,
+        };
+        // Leaves room for the Failed status message written when the change runs
+        let size = change.to_bytes().len() + SCHEDULE_STATUS_MESSAGE_RESERVE;
+        if size > SCHEDULED_CHANGE_MAX_SIZE as usize {
+            return Err(format!("Scheduled change exceeds {} bytes", SCHEDULED_CHANGE_MAX_SIZE));
+        }
+        changes.insert(change.id, change.clone());
+        Ok(change)
+    })
+}
+
+pub fn cancel_change(id: u64) -> Result<(), String> {
+    SCHEDULED_CHANGES.with(|changes| {
+        let mut changes = changes.borrow_mut();
+        let mut change = changes.get(&id).ok_or("Scheduled change not found")?;
+        if change.status != ScheduleStatus::Pending {
+            return Err(format!("Scheduled change {} is no longer pending", id));
+        }
+        change.status = ScheduleStatus::Cancelled;
+        changes.insert(id, change);
+        Ok(())
+    })
+}
+
+pub fn list_changes(pending_only: bool) -> Vec<ScheduledConfigChange> {
+    SCHEDULED_CHANGES.with(|changes| {
+        changes
+            .borrow()
+            .iter()
+            .filter(|(_, c)| !pending_only || c.status == ScheduleStatus::Pending)
+            .map(|(_, c)| c)
+            .collect()
+    })
+}
+
+fn apply(change: &ScheduledConfigChange) -> Result<(), String> {
+    match &change.action {
+        ScheduledAction::Set { content, value_type } => {
+            match buss_types::get_info_item_any(&change.key) {
+                // Existing keys go through the update path so their type and version line are kept
+                Some(existing) if existing.check_validity() && value_type.is_none() => {
+                    buss_types::update_info_item(change.key.clone(), content.clone(), None)
+                }
+                _ => buss_types::add_info_item(change.key.clone(), content.clone(), value_type.clone()),
+            }
+        }
+        ScheduledAction::Invalidate => buss_types::set_info_validity(change.key.clone(), false),
+        ScheduledAction::Revalidate => buss_types::set_info_validity(change.key.clone(), true),
+        ScheduledAction::Delete => buss_types::delete_info_item(change.key.clone()),
+    }
+}
+
+fn truncate_message(mut message: String) -> String {
+    if message.len() > SCHEDULE_STATUS_MESSAGE_RESERVE {
+        let mut end = SCHEDULE_STATUS_MESSAGE_RESERVE;
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        message.truncate(end);
+    }
+    message
+}
+
+/// Applies every pending change that is due, oldest first. Called from a timer.
+pub fn run_due_changes() -> u64 {
+    let now = ic_cdk::api::time();
+    let mut due: Vec<ScheduledConfigChange> = SCHEDULED_CHANGES.with(|changes| {
+        changes
+            .borrow()
+            .iter()
+            .filter(|(_, c)| c.status == ScheduleStatus::Pending && c.execute_at <= now)
+            .map(|(_, c)| c)
+            .collect()
+    });
+    due.sort_by_key(|c| (c.execute_at, c.id));
+
+    let mut executed = 0;
+    for mut change in due {
+        change.status = match apply(&change) {
+            Ok(_) => {
+                executed += 1;
+                ScheduleStatus::Executed
+            }
+            Err(e) => {
+                ic_cdk::println!("Scheduled change {} on {} failed: {}", change.id, change.key, e);
+                ScheduleStatus::Failed(truncate_message(e))
+            }
+        };
+        change.executed_at = Some(now);
+        SCHEDULED_CHANGES.with(|changes| {
+            changes.borrow_mut().insert(change.id, change);
+        });
+    }
+    if executed > 0 {
+        ic_cdk::println!("Applied {} scheduled config changes", executed);
+    }
+    executed
+}
@@ -0,0 +1,280 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
+use std::cell::RefCell;
+
+use crate::buss_types::get_info_by_key;
+use crate::constants::{
+    DEFAULT_STORAGE_QUOTA_FILES, DEFAULT_STORAGE_QUOTA_BYTES, DEFAULT_STORAGE_QUOTA_REFRESH_SECS,
+};
+use crate::voice_oss_type::query_stored_voice_asset_by_principal;
+use crate::entitlements::{self, ENTITLEMENT_STORAGE_BYTES, ENTITLEMENT_STORAGE_FILES};
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Storage usage of a principal together with its effective quota
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct UserStorageQuota {
+    pub principal_id: Principal,
+    pub file_count: u64,
+    pub total_bytes: u64,
+    pub max_files: u64,
+    pub max_bytes: u64,
+    // Timestamp (ns) when the license based quota was last computed
+    pub quota_refreshed_at: u64,
+    pub updated_at: u64,
+}
+
+impl Storable for UserStorageQuota {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize UserStorageQuota");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize UserStorageQuota")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 512,
+        is_fixed_size: false,
+    };
+}
+
+impl UserStorageQuota {
+    /// Counts one more file of `size` bytes if it fits in the quota
+    fn try_reserve(&mut self, size: u64) -> Result<(), String> {
+        if self.file_count >= self.max_files {
+            return Err(format!("File quota exceeded: {} of {} files used", self.file_count, self.max_files));
+        }
+        if self.total_bytes.checked_add(size).map_or(true, |total| total > self.max_bytes) {
+            return Err(format!("Storage quota exceeded: {} of {} bytes used", self.total_bytes, self.max_bytes));
+        }
+        self.file_count += 1;
+        self.total_bytes += size;
+        Ok(())
+    }
+
+    fn release(&mut self, size: u64) {
+        self.file_count = self.file_count.saturating_sub(1);
+        self.total_bytes = self.total_bytes.saturating_sub(size);
+    }
+
+    fn usage(&self) -> StorageUsage {
+        StorageUsage {
+            principal_id: self.principal_id,
+            file_count: self.file_count,
+            total_bytes: self.total_bytes,
+            max_files: self.max_files,
+            max_bytes: self.max_bytes,
+            remaining_files: self.max_files.saturating_sub(self.file_count),
+            remaining_bytes: self.max_bytes.saturating_sub(self.total_bytes),
+        }
+    }
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct StorageUsage {
+    pub principal_id: Principal,
+    pub file_count: u64,
+    pub total_bytes: u64,
+    pub max_files: u64,
+    pub max_bytes: u64,
+    pub remaining_files: u64,
+    pub remaining_bytes: u64,
+}
+
+thread_local! {
+    static USER_STORAGE_QUOTA: RefCell<StableBTreeMap<String, UserStorageQuota, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)))
+        )
+    );
+}
+
+// Malformed values are logged and replaced by the default so they cannot lock users out
+fn config_u64(key: &str, default: u64) -> u64 {
+    crate::config_types::get_config_nat_or(key, default).unwrap_or_else(|e| {
+        ic_cdk::println!("{}, using default {}", e, default);
+        default
+    })
+}
+
+/// Base quota granted to every principal as (max_files, max_bytes)
+pub fn base_quota() -> (u64, u64) {
+    (
+        config_u64("storage_quota_base_files", DEFAULT_STORAGE_QUOTA_FILES),
+        config_u64("storage_quota_base_bytes", DEFAULT_STORAGE_QUOTA_BYTES),
+    )
+}
+
+// Creates the quota record, counting assets stored before quotas existed
+fn load_or_init(principal_id: Principal) -> UserStorageQuota {
+    if let Some(quota) = USER_STORAGE_QUOTA.with(|store| store.borrow().get(&principal_id.to_text())) {
+        return quota;
+    }
+
+    let existing = query_stored_voice_asset_by_principal(principal_id);
+    let (max_files, max_bytes) = base_quota();
+    UserStorageQuota {
+        principal_id,
+        file_count: existing.len() as u64,
+        total_bytes: existing.iter().map(|data| data.size.unwrap_or(0)).sum(),
+        max_files,
+        max_bytes,
+        quota_refreshed_at: 0,
+        updated_at: ic_cdk::api::time(),
+    }
+}
+
+fn save(quota: UserStorageQuota) {
+    USER_STORAGE_QUOTA.with(|store| {
+        store.borrow_mut().insert(quota.principal_id.to_text(), quota);
+    });
+}
+
+/// Recomputes the effective quota from the base quota and the licenses the principal holds.
+/// License collections are listed in `storage_quota_license_ids`; each collection grants the
+/// extra files/bytes configured in `<collection>_quota_files` and `<collection>_quota_bytes`.
+/// Licenses in the catalog add their `storage_files` and `storage_bytes` entitlements.
+/// The result is cached for `storage_quota_refresh_secs` unless `force` is set.
+pub async fn refresh_user_quota(principal_id: Principal, force: bool) -> UserStorageQuota {
+    let now = ic_cdk::api::time();
+    let quota = load_or_init(principal_id);
+    let refresh_ns = config_u64("storage_quota_refresh_secs", DEFAULT_STORAGE_QUOTA_REFRESH_SECS) * 1_000_000_000;
+    if !force && quota.quota_refreshed_at > 0 && now.saturating_sub(quota.quota_refreshed_at) < refresh_ns {
+        return quota;
+    }
+
+    let (mut max_files, mut max_bytes) = base_quota();
+    let license_ids: Vec<String> = get_info_by_key(&"storage_quota_license_ids".to_string())
+        .map(|info| {
+            info.content
+                .split(',')
+                .map(|id| id.trim().to_string())
+                .filter(|id| !id.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if !license_ids.is_empty() {
+        match crate::license_types::get_all_user_nfts(principal_id, license_ids).await {
+            Ok(holdings) => {
+                let now_sec = now / 1_000_000_000;
+                for holding in holdings {
+                    if !holding.is_active(now_sec) {
+                        ic_cdk::println!("Licenses of {} held by {} have expired", holding.nft_colletion_id, principal_id);
+                        continue;
+                    }
+                    max_files = max_files.saturating_add(config_u64(&format!("{}_quota_files", holding.nft_colletion_id), 0));
+                    max_bytes = max_bytes.saturating_add(config_u64(&format!("{}_quota_bytes", holding.nft_colletion_id), 0));
+                }
+            }
+            Err(e) => ic_cdk::println!("Failed to load licenses for quota of {}: {}", principal_id, e),
+        }
+    }
+
+    // Catalog licenses add their storage entitlements on top
+    let entitlements = entitlements::refresh_entitlements(principal_id, force).await;
+    if let Some(files) = entitlements.get(ENTITLEMENT_STORAGE_FILES).and_then(|e| e.value) {
+        max_files = max_files.saturating_add(files);
+    }
+    if let Some(bytes) = entitlements.get(ENTITLEMENT_STORAGE_BYTES).and_then(|e| e.value) {
+        max_bytes = max_bytes.saturating_add(bytes);
+    }
+
+    // Usage may have changed while awaiting the license lookup
+    let mut quota = load_or_init(principal_id);
+    quota.max_files = max_files;
+    quota.max_bytes = max_bytes;
+    quota.quota_refreshed_at = now;
+    quota.updated_at = now;
+    save(quota.clone());
+    quota
+}
+
+/// Checks that one more file of `size` bytes fits in the quota and records the usage
+pub fn reserve_storage(principal_id: Principal, size: u64) -> Result<(), String> {
+    let mut quota = load_or_init(principal_id);
+    quota.try_reserve(size)?;
+    quota.updated_at = ic_cdk::api::time();
+    save(quota);
+    Ok(())
+}
+
+/// Returns one file of `size` bytes to the principal's quota
+pub fn release_storage(principal_id: Principal, size: u64) {
+    let mut quota = load_or_init(principal_id);
+    quota.release(size);
+    quota.updated_at = ic_cdk::api::time();
+    save(quota);
+}
+
+pub fn get_storage_usage(principal_id: Principal) -> StorageUsage {
+    load_or_init(principal_id).usage()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quota(file_count: u64, total_bytes: u64, max_files: u64, max_bytes: u64) -> UserStorageQuota {
+        UserStorageQuota {
+            principal_id: Principal::anonymous(),
+            file_count,
+            total_bytes,
+            max_files,
+            max_bytes,
+            quota_refreshed_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn reserve_fills_quota_exactly() {
+        let mut q = quota(0, 0, 2, 100);
+        assert!(q.try_reserve(60).is_ok());
+        assert!(q.try_reserve(40).is_ok());
+        assert_eq!((q.file_count, q.total_bytes), (2, 100));
+        assert!(q.try_reserve(0).is_err());
+    }
+
+    #[test]
+    fn reserve_rejects_without_changing_usage() {
+        let mut q = quota(1, 90, 5, 100);
+        assert!(q.try_reserve(11).is_err());
+        assert_eq!((q.file_count, q.total_bytes), (1, 90));
+
+        let mut q = quota(5, 0, 5, 100);
+        assert!(q.try_reserve(1).is_err());
+        assert_eq!((q.file_count, q.total_bytes), (5, 0));
+    }
+
+    #[test]
+    fn reserve_does_not_overflow() {
+        let mut q = quota(0, 1, 10, u64::MAX);
+        assert!(q.try_reserve(u64::MAX).is_err());
+        let mut q = quota(u64::MAX, 0, u64::MAX, 100);
+        assert!(q.try_reserve(1).is_err());
+    }
+
+    #[test]
+    fn release_saturates_at_zero() {
+        let mut q = quota(1, 50, 5, 100);
+        q.release(80);
+        assert_eq!((q.file_count, q.total_bytes), (0, 0));
+        q.release(10);
+        assert_eq!((q.file_count, q.total_bytes), (0, 0));
+    }
+
+    #[test]
+    fn usage_reports_remaining_without_underflow() {
+        let usage = quota(3, 70, 5, 100).usage();
+        assert_eq!((usage.remaining_files, usage.remaining_bytes), (2, 30));
+        // A downgraded quota can leave usage above the limit
+        let usage = quota(8, 150, 5, 100).usage();
+        assert_eq!((usage.remaining_files, usage.remaining_bytes), (0, 0));
+    }
+}
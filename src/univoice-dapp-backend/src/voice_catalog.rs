@@ -0,0 +1,413 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
+use std::cell::RefCell;
+
+use crate::constants::{
+    VOICE_TITLE_MAX_LEN, VOICE_DESCRIPTION_MAX_LEN, VOICE_MAX_TAGS, VOICE_TAG_MAX_LEN, VOICE_COVER_MAX_LEN,
+    VOICE_TRENDING_CANDIDATES, VOICE_READ_TOKEN_MAX_FILES,
+};
+use crate::voice_engagement;
+use crate::voice_oss_type::{
+    get_voice_asset_data, query_voice_asset_ids_by_principal, VoiceAssetData, VOICE_STATUS_ACTIVE,
+};
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Who can see a published voice asset. Unlisted assets are reachable by id but kept
+/// out of the public catalog.
+#[derive(Clone, Copy, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum VoiceVisibility {
+    Private,
+    Unlisted,
+    Public,
+}
+
+/// Presentation details a creator attaches to a voice asset
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct VoicePublication {
+    pub title: String,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub cover_image: Option<String>,
+    pub visibility: VoiceVisibility,
+    pub published_at: u64,
+}
+
+impl Storable for VoicePublication {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize VoicePublication");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize VoicePublication")
+    }
+
+    // Fits the longest title, description, tags and cover the validation accepts
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 4096,
+        is_fixed_size: false,
+    };
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PublishVoiceRequest {
+    pub title: String,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub cover_image: Option<String>,
+    pub visibility: VoiceVisibility,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, CandidType, Deserialize)]
+pub enum VoiceCatalogSort {
+    Newest,
+    Trending,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct PublicVoiceQuery {
+    pub sort: Option<VoiceCatalogSort>,
+    pub tag: Option<String>,
+    pub page: Option<u32>,
+    pub page_size: Option<u32>,
+    // `next_cursor` of the previous Newest page; takes precedence over `page`
+    pub cursor: Option<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct PublicVoicePage {
+    pub voices: Vec<PublicVoice>,
+    // Set when a Newest page is full; pass it back as `cursor` for the next page
+    pub next_cursor: Option<String>,
+}
+
+/// A published voice asset as shown to listeners
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct PublicVoice {
+    pub asset_id: u64,
+    pub owner: Principal,
+    pub title: String,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub cover_image: Option<String>,
+    pub visibility: VoiceVisibility,
+    pub published_at: u64,
+    pub duration_ms: Option<u64>,
+    pub mime_type: Option<String>,
+    pub folder_id: u32,
+    pub file_id: u32,
+    pub bucket_id: Option<Principal>,
+}
+
+impl PublicVoice {
+    fn from_data(asset_id: u64, data: VoiceAssetData, publication: VoicePublication) -> Self {
+        Self {
+            asset_id,
+            owner: data.principal_id,
+            title: publication.title,
+            description: publication.description,
+            tags: publication.tags,
+            cover_image: publication.cover_image,
+            visibility: publication.visibility,
+            published_at: publication.published_at,
+            duration_ms: data.duration_ms,
+            mime_type: data.mime_type,
+            folder_id: data.folder_id,
+            file_id: data.file_id,
+            bucket_id: data.bucket_id,
+        }
+    }
+}
+
+thread_local! {
+    // "<zero-padded published_at>:<zero-padded asset id>" -> asset id, public assets only
+    static PUBLIC_VOICES: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29)))
+        )
+    );
+
+    // "<tag>:<zero-padded published_at>:<zero-padded asset id>" -> asset id, public assets only
+    static PUBLIC_VOICES_BY_TAG: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30)))
+        )
+    );
+
+    // Asset id -> publication, kept apart so publications do not count against the
+    // voice asset record's size
+    static PUBLICATIONS: RefCell<StableBTreeMap<u64, VoicePublication, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34)))
+        )
+    );
+}
+
+pub fn get_publication(asset_id: u64) -> Option<VoicePublication> {
+    PUBLICATIONS.with(|publications| publications.borrow().get(&asset_id))
+}
+
+fn catalog_key(published_at: u64, asset_id: u64) -> String {
+    format!("{:020}:{:020}", published_at, asset_id)
+}
+
+fn tag_key(tag: &str, published_at: u64, asset_id: u64) -> String {
+    format!("{}:{}", tag, catalog_key(published_at, asset_id))
+}
+
+fn normalize_tags(tags: Vec<String>) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().trim_start_matches('#').to_lowercase();
+        if tag.is_empty() {
+            continue;
+        }
+        if tag.len() > VOICE_TAG_MAX_LEN || !tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(format!("Tag '{}' must be at most {} letters, digits, '-' or '_'", tag, VOICE_TAG_MAX_LEN));
+        }
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    if normalized.len() > VOICE_MAX_TAGS {
+        return Err(format!("At most {} tags are allowed", VOICE_MAX_TAGS));
+    }
+    Ok(normalized)
+}
+
+fn validate(request: PublishVoiceRequest) -> Result<PublishVoiceRequest, String> {
+    let title = request.title.trim().to_string();
+    if title.is_empty() || title.chars().count() > VOICE_TITLE_MAX_LEN {
+        return Err(format!("Title must be 1 to {} characters", VOICE_TITLE_MAX_LEN));
+    }
+    let description = request.description.map(|d| d.trim().to_string()).filter(|d| !d.is_empty());
+    if description.as_ref().map_or(false, |d| d.chars().count() > VOICE_DESCRIPTION_MAX_LEN) {
+        return Err(format!("Description must be at most {} characters", VOICE_DESCRIPTION_MAX_LEN));
+    }
+    let cover_image = request.cover_image.map(|c| c.trim().to_string()).filter(|c| !c.is_empty());
+    if let Some(cover) = &cover_image {
+        if cover.len() > VOICE_COVER_MAX_LEN || !cover.starts_with("https://") {
+            return Err(format!("Cover image must be an https URL of at most {} characters", VOICE_COVER_MAX_LEN));
+        }
+    }
+    Ok(PublishVoiceRequest {
+        title,
+        description,
+        tags: normalize_tags(request.tags)?,
+        cover_image,
+        visibility: request.visibility,
+    })
+}
+
+fn unindex(asset_id: u64, publication: &VoicePublication) {
+    PUBLIC_VOICES.with(|index| index.borrow_mut().remove(&catalog_key(publication.published_at, asset_id)));
+    PUBLIC_VOICES_BY_TAG.with(|index| {
+        let mut index = index.borrow_mut();
+        for tag in &publication.tags {
+            index.remove(&tag_key(tag, publication.published_at, asset_id));
+        }
+    });
+}
+
+fn index(asset_id: u64, publication: &VoicePublication) {
+    if publication.visibility != VoiceVisibility::Public {
+        return;
+    }
+    PUBLIC_VOICES.with(|index| index.borrow_mut().insert(catalog_key(publication.published_at, asset_id), asset_id));
+    PUBLIC_VOICES_BY_TAG.with(|index| {
+        let mut index = index.borrow_mut();
+        for tag in &publication.tags {
+            index.insert(tag_key(tag, publication.published_at, asset_id), asset_id);
+        }
+    });
+}
+
+/// Publishes or updates the publication of one of the owner's active voice assets.
+/// Republishing keeps the original publication time.
+pub fn publish_voice(owner: Principal, asset_id: u64, request: PublishVoiceRequest) -> Result<VoicePublication, String> {
+    let data = get_voice_asset_data(asset_id).ok_or(format!("Voice asset {} not found", asset_id))?;
+    if data.principal_id != owner {
+        return Err("Only the owner can publish a voice asset".to_string());
+    }
+    if data.status != VOICE_STATUS_ACTIVE {
+        return Err(format!("Voice asset {} is not active", asset_id));
+    }
+    let request = validate(request)?;
+
+    let now = ic_cdk::api::time();
+    let previous = get_publication(asset_id);
+    let publication = VoicePublication {
+        title: request.title,
+        description: request.description,
+        tags: request.tags,
+        cover_image: request.cover_image,
+        visibility: request.visibility,
+        published_at: previous.as_ref().map_or(now, |p| p.published_at),
+    };
+    if let Some(previous) = &previous {
+        unindex(asset_id, previous);
+    }
+    index(asset_id, &publication);
+    PUBLICATIONS.with(|publications| publications.borrow_mut().insert(asset_id, publication.clone()));
+    Ok(publication)
+}
+
+/// A published asset that is public or unlisted and still active
+pub fn get_public_voice(asset_id: u64) -> Option<PublicVoice> {
+    let data = get_voice_asset_data(asset_id)?;
+    let publication = get_publication(asset_id)?;
+    if data.status != VOICE_STATUS_ACTIVE || publication.visibility == VoiceVisibility::Private {
+        return None;
+    }
+    Some(PublicVoice::from_data(asset_id, data, publication))
+}
+
+/// Files of `owner` in `bucket` a listener may read: the one asset when it is public or
+/// unlisted, or else the owner's newest public assets. Private assets are never included.
+pub fn readable_file_ids(owner: Principal, bucket: Principal, asset_id: Option<u64>) -> Result<Vec<u32>, String> {
+    let readable = |voice: &PublicVoice| voice.owner == owner && voice.bucket_id == Some(bucket);
+    let file_ids: Vec<u32> = match asset_id {
+        Some(asset_id) => get_public_voice(asset_id).filter(readable).map(|v| v.file_id).into_iter().collect(),
+        None => query_voice_asset_ids_by_principal(owner)
+            .into_iter()
+            .rev()
+            .filter_map(listed)
+            .filter(readable)
+            .take(VOICE_READ_TOKEN_MAX_FILES)
+            .map(|v| v.file_id)
+            .collect(),
+    };
+    if file_ids.is_empty() {
+        return Err("No published voice files to read".to_string());
+    }
+    Ok(file_ids)
+}
+
+// Index entries whose asset is still active and public; trashed assets stay indexed so a
+// restore brings them back
+fn listed(asset_id: u64) -> Option<PublicVoice> {
+    get_public_voice(asset_id).filter(|v| v.visibility == VoiceVisibility::Public)
+}
+
+// Walks the catalog newest first, below `cursor` when given, and returns up to `limit`
+// listed voices after skipping `skip`, each with its catalog key. Stops as soon as the page
+// is filled, so a page costs its own size rather than the whole catalog.
+fn walk_newest(tag: Option<&str>, cursor: Option<&str>, skip: usize, limit: usize) -> Vec<(String, PublicVoice)> {
+    let visit = |entries: &mut dyn Iterator<Item = (String, u64)>, prefix_len: usize| -> Vec<(String, PublicVoice)> {
+        entries
+            .filter_map(|(key, id)| listed(id).map(|voice| (key[prefix_len..].to_string(), voice)))
+            .skip(skip)
+            .take(limit)
+            .collect()
+    };
+    match tag {
+        Some(tag) => {
+            // Tags are letters, digits, '-' and '_', so "<tag>;" sorts right after every "<tag>:" key
+            let lower = format!("{}:", tag);
+            let upper = match cursor {
+                Some(cursor) => format!("{}{}", lower, cursor),
+                None => format!("{};", tag),
+            };
+            if upper <= lower {
+                return Vec::new();
+            }
+            PUBLIC_VOICES_BY_TAG.with(|index| visit(&mut index.borrow().range(lower.clone()..upper).rev(), lower.len()))
+        }
+        None => PUBLIC_VOICES.with(|index| {
+            let index = index.borrow();
+            match cursor {
+                Some(cursor) => visit(&mut index.range(..cursor.to_string()).rev(), 0),
+                None => visit(&mut index.iter().rev(), 0),
+            }
+        }),
+    }
+}
+
+/// Hacker News style score: engagement decays with age, so new assets with some activity
+/// outrank old popular ones. Without engagement this is the newest order.
+fn trending_score(engagement: u64, published_at: u64, now: u64) -> f64 {
+    let age_hours = now.saturating_sub(published_at) as f64 / 3_600_000_000_000.0;
+    (engagement as f64 + 1.0) / (age_hours + 2.0).powf(1.5)
+}
+
+/// Public catalog page, newest or trending first, optionally for one tag. Newest pages
+/// follow `cursor`; trending ranks the most recent `VOICE_TRENDING_CANDIDATES` assets.
+pub fn list_public(query: PublicVoiceQuery) -> PublicVoicePage {
+    let page = query.page.unwrap_or(0) as usize;
+    let page_size = query.page_size.unwrap_or(20).clamp(1, 100) as usize;
+    let tag = query.tag.map(|t| t.trim().trim_start_matches('#').to_lowercase());
+
+    match query.sort.unwrap_or(VoiceCatalogSort::Newest) {
+        VoiceCatalogSort::Newest => {
+            let skip = if query.cursor.is_some() { 0 } else { page * page_size };
+            let voices = walk_newest(tag.as_deref(), query.cursor.as_deref(), skip, page_size);
+            let next_cursor = if voices.len() == page_size { voices.last().map(|(key, _)| key.clone()) } else { None };
+            PublicVoicePage { voices: voices.into_iter().map(|(_, v)| v).collect(), next_cursor }
+        }
+        VoiceCatalogSort::Trending => {
+            let now = ic_cdk::api::time();
+            let mut voices: Vec<(f64, PublicVoice)> = walk_newest(tag.as_deref(), None, 0, VOICE_TRENDING_CANDIDATES)
+                .into_iter()
+                .map(|(_, v)| (trending_score(voice_engagement::engagement_score(v.asset_id), v.published_at, now), v))
+                .collect();
+            voices.sort_by(|a, b| b.0.total_cmp(&a.0));
+            PublicVoicePage {
+                voices: voices.into_iter().map(|(_, v)| v).skip(page * page_size).take(page_size).collect(),
+                next_cursor: None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_NS: u64 = 3_600_000_000_000;
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn normalize_tags_cleans_and_dedups() {
+        assert_eq!(
+            normalize_tags(tags(&[" #Rock ", "rock", "", "lo-fi", "Jazz_2"])),
+            Ok(tags(&["rock", "lo-fi", "jazz_2"]))
+        );
+        assert_eq!(normalize_tags(tags(&["#", "  "])), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn normalize_tags_rejects_bad_tags() {
+        assert!(normalize_tags(tags(&["hip hop"])).is_err());
+        assert!(normalize_tags(tags(&["a:b"])).is_err());
+        assert!(normalize_tags(tags(&["caf\u{e9}"])).is_err());
+        assert!(normalize_tags(vec!["a".repeat(VOICE_TAG_MAX_LEN + 1)]).is_err());
+        assert!(normalize_tags(vec!["a".repeat(VOICE_TAG_MAX_LEN)]).is_ok());
+    }
+
+    #[test]
+    fn normalize_tags_limits_count_after_dedup() {
+        let many: Vec<String> = (0..=VOICE_MAX_TAGS).map(|i| format!("t{}", i)).collect();
+        assert!(normalize_tags(many).is_err());
+        let repeated: Vec<String> = (0..=VOICE_MAX_TAGS).map(|_| "same".to_string()).collect();
+        assert_eq!(normalize_tags(repeated), Ok(tags(&["same"])));
+    }
+
+    #[test]
+    fn trending_score_decays_with_age() {
+        let now = 100 * HOUR_NS;
+        assert!(trending_score(10, now, now) > trending_score(10, now - HOUR_NS, now));
+        // Without engagement the newest asset ranks first
+        assert!(trending_score(0, now - HOUR_NS, now) > trending_score(0, now - 2 * HOUR_NS, now));
+        // Enough engagement outweighs a small age difference
+        assert!(trending_score(100, now - 5 * HOUR_NS, now) > trending_score(0, now, now));
+        // A future publication time counts as brand new
+        assert_eq!(trending_score(3, now + HOUR_NS, now), trending_score(3, now, now));
+    }
+}
@@ -0,0 +1,333 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use serde::Serialize;
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Storable, StableBTreeMap, storable::Bound};
+use std::cell::RefCell;
+
+use crate::activate_types;
+use crate::config_types::get_config_nat_or;
+use crate::constants::{
+    DEFAULT_VOICE_LISTENER_REWARD_MAX_COUNT, DEFAULT_VOICE_LISTENER_REWARD_WINDOW_MAX,
+    DEFAULT_VOICE_LISTENER_REWARD_WINDOW_SECS, DEFAULT_VOICE_PLAY_DEDUP_SECS, VOICE_ENGAGEMENT_BATCH_MAX,
+};
+use crate::entitlements;
+use crate::voice_catalog;
+use crate::memory::MEMORY_MANAGER;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Weights of each counter in the trending engagement score
+const PLAY_WEIGHT: u64 = 1;
+const LISTENER_WEIGHT: u64 = 2;
+const LIKE_WEIGHT: u64 = 3;
+const SHARE_WEIGHT: u64 = 5;
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize, Serialize)]
+pub struct VoiceEngagement {
+    pub asset_id: u64,
+    pub plays: u64,
+    pub unique_listeners: u64,
+    pub likes: u64,
+    pub shares: u64,
+    // Listeners the creator has been rewarded for, bounded by voice_listener_reward_max_count
+    pub rewarded_listeners: u64,
+    pub updated_at: u64,
+    // Start of the current reward window and the listeners rewarded in it
+    pub reward_window_start: Option<u64>,
+    pub reward_window_count: Option<u64>,
+}
+
+impl Storable for VoiceEngagement {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let serialized = candid::encode_one(self).expect("Failed to serialize VoiceEngagement");
+        std::borrow::Cow::Owned(serialized)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to deserialize VoiceEngagement")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+}
+
+impl VoiceEngagement {
+    fn new(asset_id: u64) -> Self {
+        Self { asset_id, ..Default::default() }
+    }
+
+    pub fn score(&self) -> u64 {
+        self.plays.saturating_mul(PLAY_WEIGHT)
+            .saturating_add(self.unique_listeners.saturating_mul(LISTENER_WEIGHT))
+            .saturating_add(self.likes.saturating_mul(LIKE_WEIGHT))
+            .saturating_add(self.shares.saturating_mul(SHARE_WEIGHT))
+    }
+}
+
+/// A listener's own state for an asset
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct MyVoiceEngagement {
+    pub asset_id: u64,
+    pub liked: bool,
+    pub last_played_at: Option<u64>,
+}
+
+#[derive(Clone, Copy)]
+enum Activity {
+    Play,
+    Like,
+    Share,
+}
+
+impl Activity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Activity::Play => "play",
+            Activity::Like => "like",
+            Activity::Share => "share",
+        }
+    }
+}
+
+thread_local! {
+    static ENGAGEMENT: RefCell<StableBTreeMap<u64, VoiceEngagement, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31)))
+        )
+    );
+
+    // "<zero-padded asset id>:<play|like|share>:<principal>" -> time of the last counted activity.
+    // A play entry marks a unique listener, a like entry an active like.
+    static ACTIVITY: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32)))
+        )
+    );
+}
+
+fn activity_key(asset_id: u64, activity: Activity, principal: &Principal) -> String {
+    format!("{:020}:{}:{}", asset_id, activity.as_str(), principal.to_text())
+}
+
+fn last_activity(asset_id: u64, activity: Activity, principal: &Principal) -> Option<u64> {
+    ACTIVITY.with(|store| store.borrow().get(&activity_key(asset_id, activity, principal)))
+}
+
+fn set_activity(asset_id: u64, activity: Activity, principal: &Principal, at: Option<u64>) {
+    let key = activity_key(asset_id, activity, principal);
+    ACTIVITY.with(|store| match at {
+        Some(at) => store.borrow_mut().insert(key, at),
+        None => store.borrow_mut().remove(&key),
+    });
+}
+
+fn update_engagement(asset_id: u64, update: impl FnOnce(&mut VoiceEngagement)) -> VoiceEngagement {
+    ENGAGEMENT.with(|store| {
+        let mut store = store.borrow_mut();
+        let mut engagement = store.get(&asset_id).unwrap_or_else(|| VoiceEngagement::new(asset_id));
+        update(&mut engagement);
+        engagement.updated_at = ic_cdk::api::time();
+        store.insert(asset_id, engagement.clone());
+        engagement
+    })
+}
+
+pub fn get_engagement(asset_id: u64) -> VoiceEngagement {
+    ENGAGEMENT.with(|store| store.borrow().get(&asset_id)).unwrap_or_else(|| VoiceEngagement::new(asset_id))
+}
+
+/// Weighted engagement used by the trending sort
+pub fn engagement_score(asset_id: u64) -> u64 {
+    ENGAGEMENT.with(|store| store.borrow().get(&asset_id)).map_or(0, |e| e.score())
+}
+
+/// Counters for a batch of assets, in request order; unknown assets report zeros
+pub fn get_engagements(asset_ids: Vec<u64>) -> Result<Vec<VoiceEngagement>, String> {
+    if asset_ids.len() > VOICE_ENGAGEMENT_BATCH_MAX {
+        return Err(format!("At most {} asset ids per request", VOICE_ENGAGEMENT_BATCH_MAX));
+    }
+    Ok(asset_ids.into_iter().map(get_engagement).collect())
+}
+
+pub fn get_my_engagement(principal: &Principal, asset_ids: Vec<u64>) -> Result<Vec<MyVoiceEngagement>, String> {
+    if asset_ids.len() > VOICE_ENGAGEMENT_BATCH_MAX {
+        return Err(format!("At most {} asset ids per request", VOICE_ENGAGEMENT_BATCH_MAX));
+    }
+    Ok(asset_ids
+        .into_iter()
+        .map(|asset_id| MyVoiceEngagement {
+            asset_id,
+            liked: last_activity(asset_id, Activity::Like, principal).is_some(),
+            last_played_at: last_activity(asset_id, Activity::Play, principal),
+        })
+        .collect())
+}
+
+// Engagement is only counted on public or unlisted assets, and never from their owner
+fn engageable(principal: &Principal, asset_id: u64) -> Result<Principal, String> {
+    if *principal == Principal::anonymous() {
+        return Err("Anonymous callers cannot engage with voice assets".to_string());
+    }
+    let voice = voice_catalog::get_public_voice(asset_id).ok_or(format!("Voice asset {} is not published", asset_id))?;
+    Ok(voice.owner)
+}
+
+fn config_secs_ns(key: &str, default: u64) -> u64 {
+    get_config_nat_or(key, default).unwrap_or(default).saturating_mul(1_000_000_000)
+}
+
+fn dedup_window_ns() -> u64 {
+    config_secs_ns("voice_play_dedup_secs", DEFAULT_VOICE_PLAY_DEDUP_SECS)
+}
+
+// Whether an activity falls outside the dedup window of the previous counted one
+fn outside_window(last: Option<u64>, now: u64, window_ns: u64) -> bool {
+    last.map_or(true, |at| now.saturating_sub(at) >= window_ns)
+}
+
+// Start and count of the reward window after one more reward, or None when the window
+// starting at `start` has already paid `max` rewards. An elapsed window starts over at `now`.
+fn next_reward_window(start: Option<u64>, count: u64, now: u64, window_ns: u64, max: u64) -> Option<(u64, u64)> {
+    let (start, count) = match start {
+        Some(start) if now.saturating_sub(start) < window_ns => (start, count),
+        _ => (now, 0),
+    };
+    (count < max).then_some((start, count + 1))
+}
+
+/// Credits the creator `voice_listener_reward` for a new unique listener, up to
+/// `voice_listener_reward_max_count` listeners per asset and `voice_listener_reward_window_max`
+/// per asset in each `voice_listener_reward_window_secs`. Principals cost nothing to create,
+/// so these caps are what bounds the rewards an asset can collect.
+fn reward_creator(owner: &Principal, engagement: &VoiceEngagement) {
+    let reward = get_config_nat_or("voice_listener_reward", 0).unwrap_or(0);
+    if reward == 0 {
+        return;
+    }
+    let max_count = get_config_nat_or("voice_listener_reward_max_count", DEFAULT_VOICE_LISTENER_REWARD_MAX_COUNT)
+        .unwrap_or(DEFAULT_VOICE_LISTENER_REWARD_MAX_COUNT);
+    if engagement.rewarded_listeners >= max_count {
+        return;
+    }
+    let window_max = get_config_nat_or("voice_listener_reward_window_max", DEFAULT_VOICE_LISTENER_REWARD_WINDOW_MAX)
+        .unwrap_or(DEFAULT_VOICE_LISTENER_REWARD_WINDOW_MAX);
+    let Some((window_start, window_count)) = next_reward_window(
+        engagement.reward_window_start,
+        engagement.reward_window_count.unwrap_or(0),
+        ic_cdk::api::time(),
+        config_secs_ns("voice_listener_reward_window_secs", DEFAULT_VOICE_LISTENER_REWARD_WINDOW_SECS),
+        window_max,
+    ) else {
+        return;
+    };
+    let amount = entitlements::apply_reward_multiplier(owner, reward);
+    // Reward records are keyed by task id and time, so the listener number keeps ids of
+    // plays in the same block apart
+    match activate_types::add_task_reward(
+        format!("voice_listener_{}_{}", engagement.asset_id, engagement.unique_listeners),
+        owner.to_text(),
+        Nat::from(amount),
+    ) {
+        Ok(_) => {
+            update_engagement(engagement.asset_id, |e| {
+                e.rewarded_listeners += 1;
+                e.reward_window_start = Some(window_start);
+                e.reward_window_count = Some(window_count);
+            });
+        }
+        Err(e) => ic_cdk::println!("Failed to add listener reward for voice {}: {}", engagement.asset_id, e),
+    }
+}
+
+/// Counts a play unless the same principal's previous play of the asset is within
+/// `voice_play_dedup_secs`. The first play of a principal adds a unique listener and
+/// may reward the creator.
+pub fn record_play(principal: Principal, asset_id: u64) -> Result<VoiceEngagement, String> {
+    let owner = engageable(&principal, asset_id)?;
+    if principal == owner {
+        return Ok(get_engagement(asset_id));
+    }
+    let now = ic_cdk::api::time();
+    let last = last_activity(asset_id, Activity::Play, &principal);
+    if !outside_window(last, now, dedup_window_ns()) {
+        return Ok(get_engagement(asset_id));
+    }
+    set_activity(asset_id, Activity::Play, &principal, Some(now));
+    let new_listener = last.is_none();
+    let engagement = update_engagement(asset_id, |e| {
+        e.plays += 1;
+        if new_listener {
+            e.unique_listeners += 1;
+        }
+    });
+    if new_listener {
+        reward_creator(&owner, &engagement);
+    }
+    Ok(get_engagement(asset_id))
+}
+
+/// Likes or unlikes an asset; repeating the current state changes nothing
+pub fn set_like(principal: Principal, asset_id: u64, liked: bool) -> Result<VoiceEngagement, String> {
+    engageable(&principal, asset_id)?;
+    let current = last_activity(asset_id, Activity::Like, &principal).is_some();
+    if current == liked {
+        return Ok(get_engagement(asset_id));
+    }
+    set_activity(asset_id, Activity::Like, &principal, liked.then(ic_cdk::api::time));
+    Ok(update_engagement(asset_id, |e| {
+        e.likes = if liked { e.likes + 1 } else { e.likes.saturating_sub(1) };
+    }))
+}
+
+/// Counts a share, deduplicated per principal like plays
+pub fn record_share(principal: Principal, asset_id: u64) -> Result<VoiceEngagement, String> {
+    engageable(&principal, asset_id)?;
+    let now = ic_cdk::api::time();
+    if !outside_window(last_activity(asset_id, Activity::Share, &principal), now, dedup_window_ns()) {
+        return Ok(get_engagement(asset_id));
+    }
+    set_activity(asset_id, Activity::Share, &principal, Some(now));
+    Ok(update_engagement(asset_id, |e| e.shares += 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: u64 = 1_800_000_000_000;
+
+    #[test]
+    fn outside_window_counts_first_and_spaced_activity() {
+        assert!(outside_window(None, 0, WINDOW));
+        assert!(!outside_window(Some(1_000), 1_000 + WINDOW - 1, WINDOW));
+        assert!(outside_window(Some(1_000), 1_000 + WINDOW, WINDOW));
+        // Clock readings before the last activity never count twice
+        assert!(!outside_window(Some(5_000), 1_000, WINDOW));
+        assert!(outside_window(Some(5_000), 5_000, 0));
+    }
+
+    #[test]
+    fn reward_window_counts_up_to_max() {
+        assert_eq!(next_reward_window(None, 0, 100, WINDOW, 2), Some((100, 1)));
+        assert_eq!(next_reward_window(Some(100), 1, 200, WINDOW, 2), Some((100, 2)));
+        assert_eq!(next_reward_window(Some(100), 2, 300, WINDOW, 2), None);
+        assert_eq!(next_reward_window(None, 0, 100, WINDOW, 0), None);
+    }
+
+    #[test]
+    fn reward_window_restarts_when_elapsed() {
+        assert_eq!(next_reward_window(Some(100), 2, 100 + WINDOW, WINDOW, 2), Some((100 + WINDOW, 1)));
+        // A stored count without a start is treated as a new window
+        assert_eq!(next_reward_window(None, 5, 300, WINDOW, 2), Some((300, 1)));
+    }
+
+    #[test]
+    fn engagement_score_weights_counters() {
+        let engagement = VoiceEngagement { plays: 10, unique_listeners: 4, likes: 2, shares: 1, ..VoiceEngagement::new(7) };
+        assert_eq!(engagement.score(), 10 + 4 * 2 + 2 * 3 + 5);
+        let saturated = VoiceEngagement { plays: u64::MAX, shares: u64::MAX, ..VoiceEngagement::new(7) };
+        assert_eq!(saturated.score(), u64::MAX);
+    }
+}
@@ -1,25 +1,45 @@
 use candid::{CandidType, Decode, Encode, Principal};
 use serde::{Deserialize, Serialize};
 use ic_stable_structures::{
-    memory_manager::{MemoryId, MemoryManager, VirtualMemory},
-    DefaultMemoryImpl, StableVec, storable::Bound, Storable,
+    memory_manager::{MemoryId, VirtualMemory},
+    DefaultMemoryImpl, StableBTreeMap, StableVec, storable::Bound, Storable,
 };
 use std::cell::RefCell;
 use std::borrow::Cow;
 use ic_cdk::api::time;
+use sha2::{Digest, Sha256};
+
+use crate::buss_types::get_info_by_key;
+use crate::voice_catalog::{VoicePublication, VoiceVisibility};
+use crate::config_types::get_config_nat_or;
+use crate::constants::{
+    DEFAULT_VOICE_MAX_SIZE_BYTES, DEFAULT_VOICE_MAX_DURATION_MS, DEFAULT_VOICE_MIN_SAMPLE_RATE,
+    DEFAULT_VOICE_MAX_SAMPLE_RATE, DEFAULT_VOICE_MAX_CHANNELS, DEFAULT_VOICE_ALLOWED_MIME_TYPES,
+    DEFAULT_VOICE_TRASH_RETENTION_DAYS, VOICE_CUSTOM_MAX_ENTRIES, VOICE_CUSTOM_MAX_BYTES,
+};
+use crate::ic_oss_dapp::BucketFileInfo;
+use crate::memory::MEMORY_MANAGER;
 
 pub type Result<T, E = String> = std::result::Result<T, E>;
 
+// Slot size of a VoiceAssetData in the stable vector. The vector is laid out with this size,
+// so it cannot change without migrating the data; writes that do not fit are rejected.
+const VOICE_ASSET_DATA_MAX_SIZE: u32 = 5120;
+
 // Memory management setup
 thread_local! {
-    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
-        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
-
     static VOICE_ASSET_DATA: RefCell<StableVec<VoiceAssetData, VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
         StableVec::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4)))
         ).expect("Failed to initialize VOICE_ASSET_DATA")
     );
+
+    // Content hash index: "<owner>:<sha256>" -> asset index
+    static VOICE_HASH_INDEX: RefCell<StableBTreeMap<String, u64, VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10)))
+        )
+    );
 }
 
 /// Voice data structure that stores principal ID, folder ID, and file ID
@@ -31,7 +51,7 @@ pub struct VoiceAssetData {
     pub folder_id: u32,
     /// File ID of the voice data
     pub file_id: u32,
-    /// Status flag (0: active, -1: deleted)
+    /// Status flag (0: active, -1: trashed, -2: purged, -3: purge in progress)
     pub status: i32,
     /// Timestamp when the data was created
     pub created_at: u64,
@@ -39,6 +59,24 @@ pub struct VoiceAssetData {
     pub updated_at: Option<u64>,
     /// Optional custom metadata
     pub custom: Option<Vec<(String, MetadataValue)>>,
+    /// Duration of the recording in milliseconds
+    pub duration_ms: Option<u64>,
+    /// MIME type of the stored file (e.g. audio/wav)
+    pub mime_type: Option<String>,
+    /// Audio codec (e.g. pcm_s16le, opus)
+    pub codec: Option<String>,
+    /// Sample rate in Hz
+    pub sample_rate: Option<u32>,
+    /// Number of audio channels
+    pub channels: Option<u16>,
+    /// Size of the file in bytes
+    pub size: Option<u64>,
+    /// Hex encoded SHA-256 hash of the file content
+    pub content_hash: Option<String>,
+    /// Timestamp when the data was moved to the trash
+    pub trashed_at: Option<u64>,
+    /// ic-oss bucket canister holding the file
+    pub bucket_id: Option<Principal>,
 }
 
 impl Default for VoiceAssetData {
@@ -51,6 +89,15 @@ impl Default for VoiceAssetData {
             created_at: 0,
             updated_at: None,
             custom: None,
+            duration_ms: None,
+            mime_type: None,
+            codec: None,
+            sample_rate: None,
+            channels: None,
+            size: None,
+            content_hash: None,
+            trashed_at: None,
+            bucket_id: None,
         }
     }
 }
@@ -71,11 +118,17 @@ impl Storable for VoiceAssetData {
     }
 
     const BOUND: Bound = Bound::Bounded {
-        max_size: 5120, // 5KB should be sufficient for this struct
+        max_size: VOICE_ASSET_DATA_MAX_SIZE,
         is_fixed_size: false,
     };
 }
 
+pub const VOICE_STATUS_ACTIVE: i32 = 0;
+pub const VOICE_STATUS_TRASHED: i32 = -1;
+pub const VOICE_STATUS_PURGED: i32 = -2;
+// The bucket file is being deleted; the asset can no longer be restored
+pub const VOICE_STATUS_PURGING: i32 = -3;
+
 /// Metadata value types
 #[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
 pub enum MetadataValue {
@@ -85,15 +138,245 @@ pub enum MetadataValue {
     Text(String),
 }
 
+/// Typed metadata supplied by the uploader
+#[derive(Debug, Clone, Default, CandidType, Deserialize, Serialize)]
+pub struct VoiceFileMetadata {
+    pub duration_ms: Option<u64>,
+    pub mime_type: Option<String>,
+    pub codec: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    pub size: Option<u64>,
+    pub content_hash: Option<String>,
+}
+
+/// Limits applied to uploaded voice metadata, read from CommonInfoCfg
+#[derive(Debug, Clone)]
+pub struct VoiceUploadLimits {
+    pub max_size_bytes: u64,
+    pub max_duration_ms: u64,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub max_channels: u16,
+    pub allowed_mime_types: Vec<String>,
+}
+
+impl VoiceUploadLimits {
+    pub fn load() -> Result<Self, String> {
+        let allowed_mime_types = get_info_by_key(&"voice_allowed_mime_types".to_string())
+            .map(|info| info.content)
+            .unwrap_or_else(|| DEFAULT_VOICE_ALLOWED_MIME_TYPES.to_string())
+            .split(',')
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        Ok(Self {
+            max_size_bytes: config_number("voice_max_size_bytes", DEFAULT_VOICE_MAX_SIZE_BYTES)?,
+            max_duration_ms: config_number("voice_max_duration_ms", DEFAULT_VOICE_MAX_DURATION_MS)?,
+            min_sample_rate: config_number("voice_min_sample_rate", DEFAULT_VOICE_MIN_SAMPLE_RATE)?,
+            max_sample_rate: config_number("voice_max_sample_rate", DEFAULT_VOICE_MAX_SAMPLE_RATE)?,
+            max_channels: config_number("voice_max_channels", DEFAULT_VOICE_MAX_CHANNELS)?,
+            allowed_mime_types,
+        })
+    }
+}
+
+// Reads a numeric limit, defaulting when the key is missing and failing when it is malformed
+fn config_number<T: Into<u64> + TryFrom<u64>>(key: &str, default: T) -> Result<T, String> {
+    let value = get_config_nat_or(key, default.into())?;
+    T::try_from(value).map_err(|_| format!("Config key {}: {} is out of range", key, value))
+}
+
+/// Computes the hex encoded SHA-256 hash of the content
+pub fn compute_content_hash(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+/// Validates uploaded metadata against the configured limits.
+/// When content is provided, its size and hash are computed and must match any declared values.
+pub fn validate_voice_metadata(
+    metadata: Option<VoiceFileMetadata>,
+    content: &[u8],
+    limits: &VoiceUploadLimits,
+) -> Result<VoiceFileMetadata, String> {
+    let mut metadata = metadata.unwrap_or_default();
+
+    if let Some(hash) = metadata.content_hash.as_mut() {
+        *hash = hash.trim().to_lowercase();
+        if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err("Content hash must be a hex encoded SHA-256 digest".to_string());
+        }
+    }
+
+    if !content.is_empty() {
+        let size = content.len() as u64;
+        if let Some(declared) = metadata.size {
+            if declared != size {
+                return Err(format!("Declared size {} does not match content size {}", declared, size));
+            }
+        }
+        metadata.size = Some(size);
+
+        let hash = compute_content_hash(content);
+        if let Some(declared) = &metadata.content_hash {
+            if *declared != hash {
+                return Err("Declared content hash does not match content".to_string());
+            }
+        }
+        metadata.content_hash = Some(hash);
+    }
+
+    if let Some(size) = metadata.size {
+        if size == 0 || size > limits.max_size_bytes {
+            return Err(format!("File size {} exceeds limit of {} bytes", size, limits.max_size_bytes));
+        }
+    }
+
+    if let Some(duration) = metadata.duration_ms {
+        if duration == 0 || duration > limits.max_duration_ms {
+            return Err(format!("Duration {}ms exceeds limit of {}ms", duration, limits.max_duration_ms));
+        }
+    }
+
+    if let Some(mime_type) = metadata.mime_type.as_mut() {
+        *mime_type = mime_type.trim().to_lowercase();
+        if !limits.allowed_mime_types.iter().any(|m| m == mime_type) {
+            return Err(format!("MIME type {} is not allowed", mime_type));
+        }
+    }
+
+    if let Some(sample_rate) = metadata.sample_rate {
+        if sample_rate < limits.min_sample_rate || sample_rate > limits.max_sample_rate {
+            return Err(format!("Sample rate {}Hz is outside {}-{}Hz", sample_rate, limits.min_sample_rate, limits.max_sample_rate));
+        }
+    }
+
+    if let Some(channels) = metadata.channels {
+        if channels == 0 || channels > limits.max_channels {
+            return Err(format!("Channel count {} exceeds limit of {}", channels, limits.max_channels));
+        }
+    }
+
+    if let Some(codec) = metadata.codec.as_mut() {
+        *codec = codec.trim().to_lowercase();
+        if codec.is_empty() {
+            metadata.codec = None;
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// Checks custom key/value pairs against the entry and size limits
+pub fn validate_custom_metadata(custom: &[(String, String)]) -> Result<(), String> {
+    if custom.len() > VOICE_CUSTOM_MAX_ENTRIES {
+        return Err(format!("At most {} custom metadata entries are allowed", VOICE_CUSTOM_MAX_ENTRIES));
+    }
+    if custom.iter().any(|(k, _)| k.trim().is_empty()) {
+        return Err("Custom metadata keys must not be empty".to_string());
+    }
+    let total: usize = custom.iter().map(|(k, v)| k.len() + v.len()).sum();
+    if total > VOICE_CUSTOM_MAX_BYTES {
+        return Err(format!("Custom metadata of {} bytes exceeds {} bytes", total, VOICE_CUSTOM_MAX_BYTES));
+    }
+    Ok(())
+}
+
+/// Checks validated metadata against the file as stored in the bucket: the file must sit in
+/// the owner's folder and be fully uploaded, and the size counted by the bucket replaces the
+/// declared one for the size limit and quota. A hash declared without content is only used to
+/// match the owner's own re-uploads.
+pub fn verify_stored_file(
+    mut metadata: VoiceFileMetadata,
+    folder_id: u32,
+    stored: &BucketFileInfo,
+    limits: &VoiceUploadLimits,
+) -> Result<VoiceFileMetadata, String> {
+    if stored.parent != folder_id {
+        return Err(format!("File is not in folder {}", folder_id));
+    }
+    if stored.filled != stored.size {
+        return Err(format!("File upload is incomplete: {} of {} bytes stored", stored.filled, stored.size));
+    }
+    if let Some(declared) = metadata.size {
+        if declared != stored.filled {
+            return Err(format!("Declared size {} does not match stored size {}", declared, stored.filled));
+        }
+    }
+    if stored.filled == 0 || stored.filled > limits.max_size_bytes {
+        return Err(format!("File size {} exceeds limit of {} bytes", stored.filled, limits.max_size_bytes));
+    }
+    metadata.size = Some(stored.filled);
+    Ok(metadata)
+}
+
+// Rejects records that would not fit the stable vector slot instead of trapping on write
+fn check_encoded_size(data: &VoiceAssetData) -> Result<(), String> {
+    let size = Encode!(data).map_err(|e| format!("Failed to encode voice asset data: {}", e))?.len();
+    if size > VOICE_ASSET_DATA_MAX_SIZE as usize {
+        return Err(format!("Voice asset record of {} bytes exceeds {} bytes", size, VOICE_ASSET_DATA_MAX_SIZE));
+    }
+    Ok(())
+}
+
+/// How uploads with an already known content hash are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupPolicy {
+    /// Reject the upload with an error naming the existing asset
+    Reject,
+    /// Skip storing and return the existing asset id
+    ReturnExisting,
+    /// Store the duplicate as a new asset
+    Allow,
+}
+
+impl DedupPolicy {
+    /// Reads the policy from the `voice_dedup_policy` config key, defaulting to ReturnExisting
+    pub fn load() -> Self {
+        match get_info_by_key(&"voice_dedup_policy".to_string())
+            .map(|info| info.content.trim().to_lowercase())
+            .as_deref()
+        {
+            Some("reject") => DedupPolicy::Reject,
+            Some("allow") => DedupPolicy::Allow,
+            _ => DedupPolicy::ReturnExisting,
+        }
+    }
+}
+
+fn hash_index_key(owner: &Principal, content_hash: &str) -> String {
+    format!("{}:{}", owner.to_text(), content_hash.to_lowercase())
+}
+
+/// Finds an active asset of the owner with the given content hash
+pub fn find_duplicate_asset(owner: Principal, content_hash: &str) -> Option<u64> {
+    let index = VOICE_HASH_INDEX.with(|idx| idx.borrow().get(&hash_index_key(&owner, content_hash)))?;
+    get_voice_asset_data(index)
+        .filter(|data| data.status == VOICE_STATUS_ACTIVE && data.principal_id == owner)
+        .map(|_| index)
+}
+
 /// Stores a new VoiceAssetData in stable memory
 pub fn store_voice_asset_data(data: VoiceAssetData) -> Result<u64, String> {
-    VOICE_ASSET_DATA.with(|storage| {
+    check_encoded_size(&data)?;
+    let index = VOICE_ASSET_DATA.with(|storage| {
         let storage = storage.borrow_mut();
         let index = storage.len();
         storage.push(&data)
             .map_err(|e| format!("Failed to store voice asset data: {}", e))?;
-        Ok(index)
-    })
+        Ok::<u64, String>(index)
+    })?;
+
+    // Keep the first active asset for a hash as the canonical one
+    if let Some(hash) = &data.content_hash {
+        if find_duplicate_asset(data.principal_id, hash).is_none() {
+            VOICE_HASH_INDEX.with(|idx| {
+                idx.borrow_mut().insert(hash_index_key(&data.principal_id, hash), index);
+            });
+        }
+    }
+    Ok(index)
 }
 
 /// Retrieves VoiceAssetData by index from stable memory
@@ -106,6 +389,7 @@ pub fn get_voice_asset_data(index: u64) -> Option<VoiceAssetData> {
 
 /// Updates existing VoiceAssetData in stable memory
 pub fn update_voice_asset_data(index: u64, data: VoiceAssetData) -> Result<(), String> {
+    check_encoded_size(&data)?;
     VOICE_ASSET_DATA.with(|storage| {
         let mut storage = storage.borrow_mut();
         if index >= storage.len() {
@@ -127,29 +411,215 @@ pub fn list_voice_asset_data() -> Vec<VoiceAssetData> {
     })
 }
 
-/// Deletes VoiceAssetData by index (marks as deleted)
+/// Deletes VoiceAssetData by index (moves it to the trash)
 pub fn delete_voice_asset_data(index: u64) -> Result<(), String> {
-    VOICE_ASSET_DATA.with(|storage| {
+    let data = VOICE_ASSET_DATA.with(|storage| -> Result<VoiceAssetData, String> {
         let mut storage = storage.borrow_mut();
         if index >= storage.len() {
             return Err("Index out of bounds".to_string());
         }
         let mut data = storage.get(index).ok_or("Data not found")?;
-        data.status = -1;
-        data.updated_at = Some(time());
+        if data.status != VOICE_STATUS_ACTIVE {
+            return Err("Voice asset is not active".to_string());
+        }
+        let now = time();
+        data.status = VOICE_STATUS_TRASHED;
+        data.trashed_at = Some(now);
+        data.updated_at = Some(now);
         
         storage.set(index, &data);
-        Ok(())
+
+        Ok(data)
+    })?;
+
+    // Duplicates stored under the Allow policy take over as the canonical asset
+    if let Some(hash) = &data.content_hash {
+        let key = hash_index_key(&data.principal_id, hash);
+        if VOICE_HASH_INDEX.with(|idx| idx.borrow().get(&key)) == Some(index) {
+            match find_active_with_hash(data.principal_id, hash) {
+                Some(other) => VOICE_HASH_INDEX.with(|idx| idx.borrow_mut().insert(key, other)),
+                None => VOICE_HASH_INDEX.with(|idx| idx.borrow_mut().remove(&key)),
+            };
+        }
+    }
+    Ok(())
+}
+
+// Oldest active asset of the owner with the content hash, found by a scan
+fn find_active_with_hash(owner: Principal, content_hash: &str) -> Option<u64> {
+    VOICE_ASSET_DATA.with(|storage| {
+        let storage = storage.borrow();
+        (0..storage.len()).find(|&i| {
+            storage.get(i).map_or(false, |data| {
+                data.principal_id == owner
+                    && data.status == VOICE_STATUS_ACTIVE
+                    && data.content_hash.as_deref().map_or(false, |h| h.eq_ignore_ascii_case(content_hash))
+            })
+        })
     })
 }
 
+/// Retention of trashed voice assets in nanoseconds, from `voice_trash_retention_days`
+pub fn trash_retention_ns() -> Result<u64, String> {
+    Ok(config_number("voice_trash_retention_days", DEFAULT_VOICE_TRASH_RETENTION_DAYS)? * 24 * 3600 * 1_000_000_000)
+}
+
+// Records trashed before the lifecycle existed have no trashed_at
+fn trashed_since(data: &VoiceAssetData) -> u64 {
+    data.trashed_at.or(data.updated_at).unwrap_or(data.created_at)
+}
+
+/// Restores a trashed VoiceAssetData while it is still within the retention period
+pub fn restore_voice_asset_data(index: u64) -> Result<(), String> {
+    let now = time();
+    let data = VOICE_ASSET_DATA.with(|storage| -> Result<VoiceAssetData, String> {
+        let mut storage = storage.borrow_mut();
+        let mut data = storage.get(index).ok_or("Data not found")?;
+        if data.status == VOICE_STATUS_PURGING {
+            return Err("Voice asset is being purged".to_string());
+        }
+        if data.status != VOICE_STATUS_TRASHED {
+            return Err("Voice asset is not in the trash".to_string());
+        }
+        if now.saturating_sub(trashed_since(&data)) > trash_retention_ns()? {
+            return Err("Voice asset has expired from the trash".to_string());
+        }
+        data.status = VOICE_STATUS_ACTIVE;
+        data.trashed_at = None;
+        data.updated_at = Some(now);
+        storage.set(index, &data);
+        Ok(data)
+    })?;
+
+    if let Some(hash) = &data.content_hash {
+        if find_duplicate_asset(data.principal_id, hash).is_none() {
+            VOICE_HASH_INDEX.with(|idx| {
+                idx.borrow_mut().insert(hash_index_key(&data.principal_id, hash), index);
+            });
+        }
+    }
+    Ok(())
+}
+
+// Moves an asset from one of the `from` statuses to `to`
+fn transition_status(index: u64, from: &[i32], to: i32, err: &str) -> Result<VoiceAssetData, String> {
+    VOICE_ASSET_DATA.with(|storage| {
+        let storage = storage.borrow_mut();
+        let mut data = storage.get(index).ok_or("Data not found")?;
+        if !from.contains(&data.status) {
+            return Err(err.to_string());
+        }
+        data.status = to;
+        data.updated_at = Some(time());
+        storage.set(index, &data);
+        Ok(data)
+    })
+}
+
+/// Marks a VoiceAssetData as purged once its file is gone from the bucket
+pub fn mark_voice_asset_purged(index: u64) -> Result<VoiceAssetData, String> {
+    transition_status(index, &[VOICE_STATUS_PURGING], VOICE_STATUS_PURGED, "Voice asset is not being purged")
+}
+
+/// Lists indexes of trashed assets whose retention period has passed
+pub fn list_expired_trash(now: u64) -> Result<Vec<u64>, String> {
+    let retention = trash_retention_ns()?;
+    Ok(VOICE_ASSET_DATA.with(|storage| {
+        let storage = storage.borrow();
+        (0..storage.len())
+            .filter(|&i| {
+                storage.get(i).map_or(false, |data| {
+                    (data.status == VOICE_STATUS_TRASHED || data.status == VOICE_STATUS_PURGING)
+                        && now.saturating_sub(trashed_since(&data)) > retention
+                })
+            })
+            .collect()
+    }))
+}
+
+/// Deletes the file of a trashed asset from the ic-oss bucket, then marks it purged and reclaims
+/// quota. The asset is locked in the purging state before the bucket call so it cannot be
+/// restored meanwhile. A failed delete may still have removed the file, so the asset stays
+/// locked; deleting again is harmless and the timer retries once the retention has passed.
+pub async fn purge_voice_asset(index: u64) -> Result<(), String> {
+    let data = get_voice_asset_data(index).ok_or("Data not found")?;
+
+    // Assets stored before bucket sharding live on the primary bucket
+    let bucket = match data.bucket_id {
+        Some(bucket) => bucket,
+        None => {
+            let bucket_id = crate::buss_types::get_canister_id("bulklet")
+                .ok_or("Bulklet canister ID not configured")?;
+            Principal::from_text(&bucket_id)
+                .map_err(|e| format!("Invalid bucket principal: {}", e))?
+        }
+    };
+    transition_status(
+        index,
+        &[VOICE_STATUS_TRASHED, VOICE_STATUS_PURGING],
+        VOICE_STATUS_PURGING,
+        "Voice asset is not in the trash",
+    )?;
+    crate::ic_oss_dapp::delete_bucket_file(bucket, data.file_id).await?;
+
+    // Only the purge that finishes first releases the storage
+    let data = mark_voice_asset_purged(index)?;
+    crate::storage_quota::release_storage(data.principal_id, data.size.unwrap_or(0));
+    Ok(())
+}
+
+/// Purges every trashed asset whose retention period has passed. Called from a timer.
+pub async fn purge_expired_trash() -> u64 {
+    let expired = match list_expired_trash(time()) {
+        Ok(expired) => expired,
+        Err(e) => {
+            ic_cdk::println!("Failed to list expired voice trash: {}", e);
+            return 0;
+        }
+    };
+    let mut purged = 0;
+    for index in expired {
+        match purge_voice_asset(index).await {
+            Ok(_) => purged += 1,
+            Err(e) => ic_cdk::println!("Failed to purge voice asset {}: {}", index, e),
+        }
+    }
+    if purged > 0 {
+        ic_cdk::println!("Purged {} expired voice assets from the trash", purged);
+    }
+    purged
+}
+
 /// Queries VoiceAssetData by principal_id
 pub fn query_voice_asset_by_principal(principal_id: Principal) -> Vec<VoiceAssetData> {
     VOICE_ASSET_DATA.with(|storage| {
         let storage = storage.borrow();
         (0..storage.len())
             .filter_map(|i| storage.get(i))
-            .filter(|data| data.principal_id == principal_id && data.status != -1)
+            .filter(|data| data.principal_id == principal_id && data.status == VOICE_STATUS_ACTIVE)
+            .collect()
+    })
+}
+
+/// Asset ids of a principal's active VoiceAssetData, oldest first
+pub fn query_voice_asset_ids_by_principal(principal_id: Principal) -> Vec<u64> {
+    VOICE_ASSET_DATA.with(|storage| {
+        let storage = storage.borrow();
+        (0..storage.len())
+            .filter(|&i| {
+                storage.get(i).map_or(false, |data| data.principal_id == principal_id && data.status == VOICE_STATUS_ACTIVE)
+            })
+            .collect()
+    })
+}
+
+/// Queries VoiceAssetData of a principal that still occupy bucket storage (active or trashed)
+pub fn query_stored_voice_asset_by_principal(principal_id: Principal) -> Vec<VoiceAssetData> {
+    VOICE_ASSET_DATA.with(|storage| {
+        let storage = storage.borrow();
+        (0..storage.len())
+            .filter_map(|i| storage.get(i))
+            .filter(|data| data.principal_id == principal_id && data.status != VOICE_STATUS_PURGED)
             .collect()
     })
 }
@@ -160,26 +630,142 @@ pub fn query_voice_asset_by_folder(folder_id: u32) -> Vec<VoiceAssetData> {
         let storage = storage.borrow();
         (0..storage.len())
             .filter_map(|i| storage.get(i))
-            .filter(|data| data.folder_id == folder_id && data.status != -1)
+            .filter(|data| data.folder_id == folder_id && data.status == VOICE_STATUS_ACTIVE)
             .collect()
     })
 }
 
+/// Optional filters on the typed voice metadata
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct VoiceFileFilter {
+    pub mime_type: Option<String>,
+    pub codec: Option<String>,
+    pub min_duration_ms: Option<u64>,
+    pub max_duration_ms: Option<u64>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub min_sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    pub content_hash: Option<String>,
+}
+
+impl VoiceFileFilter {
+    pub fn matches(&self, data: &VoiceAssetData) -> bool {
+        fn eq_ignore_case(expected: &Option<String>, actual: &Option<String>) -> bool {
+            match expected {
+                Some(expected) => actual.as_ref().map_or(false, |a| a.eq_ignore_ascii_case(expected)),
+                None => true,
+            }
+        }
+
+        if !eq_ignore_case(&self.mime_type, &data.mime_type)
+            || !eq_ignore_case(&self.codec, &data.codec)
+            || !eq_ignore_case(&self.content_hash, &data.content_hash)
+        {
+            return false;
+        }
+        if let Some(min) = self.min_duration_ms {
+            if data.duration_ms.map_or(true, |d| d < min) {
+                return false;
+            }
+        }
+        if let Some(max) = self.max_duration_ms {
+            if data.duration_ms.map_or(true, |d| d > max) {
+                return false;
+            }
+        }
+        if let Some(min) = self.min_size {
+            if data.size.map_or(true, |s| s < min) {
+                return false;
+            }
+        }
+        if let Some(max) = self.max_size {
+            if data.size.map_or(true, |s| s > max) {
+                return false;
+            }
+        }
+        if let Some(min) = self.min_sample_rate {
+            if data.sample_rate.map_or(true, |r| r < min) {
+                return false;
+            }
+        }
+        if let Some(channels) = self.channels {
+            if data.channels != Some(channels) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
 #[derive(CandidType, Deserialize, Clone)]
 pub struct ListVoiceOssParams {
     pub principal_id: Option<Principal>,
     pub folder_id: Option<u32>,
     pub prev: Option<u64>,
     pub take: Option<u32>,
+    pub filter: Option<VoiceFileFilter>,
 }
 
 #[derive(CandidType, Deserialize, Clone)]
 pub struct VoiceOssInfo {
+    pub asset_id: u64,
     pub file_id: u32,
     pub status: i32,
     pub created_at: u64,
     pub updated_at: Option<u64>,
     pub custom: Option<Vec<(String, MetadataValue)>>,
+    pub duration_ms: Option<u64>,
+    pub mime_type: Option<String>,
+    pub codec: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    pub size: Option<u64>,
+    pub content_hash: Option<String>,
+    pub trashed_at: Option<u64>,
+    pub bucket_id: Option<Principal>,
+    pub publication: Option<VoicePublication>,
+}
+
+impl VoiceOssInfo {
+    // Publication details of private and unlisted assets are only shown to their owner
+    fn from_data(asset_id: u64, data: VoiceAssetData, viewer: Principal) -> Self {
+        let publication = crate::voice_catalog::get_publication(asset_id)
+            .filter(|p| p.visibility == VoiceVisibility::Public || data.principal_id == viewer);
+        VoiceOssInfo {
+            asset_id,
+            file_id: data.file_id,
+            status: data.status,
+            created_at: data.created_at,
+            updated_at: data.updated_at,
+            custom: data.custom,
+            duration_ms: data.duration_ms,
+            mime_type: data.mime_type,
+            codec: data.codec,
+            sample_rate: data.sample_rate,
+            channels: data.channels,
+            size: data.size,
+            content_hash: data.content_hash,
+            trashed_at: data.trashed_at,
+            bucket_id: data.bucket_id,
+            publication,
+        }
+    }
+}
+
+/// Lists trashed voice assets of a principal, newest first
+pub fn list_voice_trash(principal_id: Principal, skip: u64, take: u32) -> Vec<VoiceOssInfo> {
+    VOICE_ASSET_DATA.with(|storage| {
+        let storage = storage.borrow();
+        (0..storage.len())
+            .rev()
+            .filter_map(|i| storage.get(i).map(|data| (i, data)))
+            .filter(|(_, data)| data.principal_id == principal_id && data.status == VOICE_STATUS_TRASHED)
+            .skip(skip as usize)
+            .take(take as usize)
+            .map(|(i, data)| VoiceOssInfo::from_data(i, data, principal_id))
+            .collect()
+    })
 }
 
 pub fn list_voice_files(params: ListVoiceOssParams) -> Result<Vec<VoiceOssInfo>, String> {
@@ -192,8 +778,8 @@ pub fn list_voice_files(params: ListVoiceOssParams) -> Result<Vec<VoiceOssInfo>,
         
         for i in 0..len {
             if let Some(data) = storage.get(i) {
-                if data.status == -1 {
-                    continue; // Skip deleted entries
+                if data.status != VOICE_STATUS_ACTIVE {
+                    continue; // Skip trashed and purged entries
                 }
                 
                 if let Some(pid) = params.principal_id {
@@ -208,6 +794,12 @@ pub fn list_voice_files(params: ListVoiceOssParams) -> Result<Vec<VoiceOssInfo>,
                     }
                 }
                 
+                if let Some(filter) = &params.filter {
+                    if !filter.matches(&data) {
+                        continue;
+                    }
+                }
+                
                 if let Some(prev) = params.prev {
                     if i <= prev {
                         continue;
@@ -220,13 +812,7 @@ pub fn list_voice_files(params: ListVoiceOssParams) -> Result<Vec<VoiceOssInfo>,
                     }
                 }
                 
-                results.push(VoiceOssInfo {
-                    file_id: data.file_id,
-                    status: data.status,
-                    created_at: data.created_at,
-                    updated_at: data.updated_at,
-                    custom: data.custom,
-                });
+                results.push(VoiceOssInfo::from_data(i, data, ic_cdk::caller()));
                 count += 1;
             }
         }